@@ -1,37 +1,52 @@
-use mapper::{Mapper, ROM_SIZE};
-use monitor::Monitor;
+use mapper::Mapper;
+use monitor::{Monitor, RenderMode, ShotSize, FRAME_WIDTH, FRAME_HEIGHT};
+use palette::Palette;
+use recorder::{Recorder, Format};
+use video_output::{VideoOutput, SdlOutput};
+use char_rom::CharRom;
 use input::{Input, KeyboardInput};
-use peripheral_card::{LanguageCard, DiskII};
+use peripheral_card::{LanguageCard, DiskII, SoftCard, Videoterm, Saturn128K, SerialPort};
+use peripheral_card::videoterm;
+use peripheral_card::language_card::ROM_SIZE;
+use config::{MachineConfig, CardConfig, CardKind, ConfigError};
 
-use r6502::cpu6502::Cpu6502;
+use cpu::Cpu;
+use scanner;
 
 use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
 use std::thread;
 use std::time::{Instant, Duration};
+use std::cmp;
 
 use sdl2;
 
+/* How long a bus master card runs before cards get ticked again */
+const BUS_MASTER_SLICE: u64 = 64;
+
 pub struct AppleII<'a> {
-    cpu: Cpu6502<Mapper<'a>>,
+    cpu: Cpu<Mapper<'a>>,
     monitor: Monitor<'a>,
-    input: Input,
+    /* None when running headless */
+    input: Option<Input>,
     paused: bool,
+    /* NMI is edge triggered, so remember the last line state */
+    nmi_line: bool,
+    /* open apple is held through the reset handler on a forced reboot,
+     * and this is the state to put back afterwards
+     */
+    held_open_apple: Option<bool>,
 }
 
 impl<'a> AppleII<'a> {
-    pub fn new(rom: [u8; ROM_SIZE]) -> AppleII<'a> {
-        let disk_file = fs::File::open("diskii.img").expect("Disk file not found.");
-
-        let mut dc = DiskII::new();
-        dc.set_first_disk(disk_file);
-
-        let lc = LanguageCard::new(rom);
-
-        let mut map = Mapper::new(rom);
-        info!("Adding card lang");
-        map.add_card(lc, 0);
-        info!("Adding card disk");
-        map.add_card(dc, 6);
+    pub fn new(rom: Vec<u8>,
+               config: &MachineConfig,
+               render_mode: RenderMode,
+               palette: Palette)
+               -> Result<AppleII<'a>, ConfigError> {
+        let map = try!(AppleII::build_mapper(rom, config));
+        let chars = try!(AppleII::char_rom(config));
 
         let sdl_context = sdl2::init().expect("Could not init SDL2.");
         let sdl_video = sdl_context.video()
@@ -39,35 +54,147 @@ impl<'a> AppleII<'a> {
         let sdl_events = sdl_context.event_pump()
             .expect("Could not event pump.");
         let sdl_keyboard = sdl_context.keyboard();
+        let sdl_joystick = sdl_context.joystick()
+            .expect("Could not init SDL2 joystick.");
+
+        let output = SdlOutput::new(sdl_video, FRAME_WIDTH, FRAME_HEIGHT);
+        let monitor = Monitor::new(Box::new(output), chars, render_mode, palette);
+        let input = Input::new(sdl_events, sdl_keyboard, sdl_joystick);
+        Ok(AppleII::from_parts(map, monitor, Some(input)))
+    }
+
+    /* A machine with no window or keyboard that draws to `output`,
+     * driven by run_frames() instead of run().
+     */
+    pub fn headless(rom: Vec<u8>,
+                    config: &MachineConfig,
+                    output: Box<VideoOutput + 'a>,
+                    render_mode: RenderMode,
+                    palette: Palette)
+                    -> Result<AppleII<'a>, ConfigError> {
+        let map = try!(AppleII::build_mapper(rom, config));
+        let chars = try!(AppleII::char_rom(config));
+        let monitor = Monitor::new(output, chars, render_mode, palette);
+        Ok(AppleII::from_parts(map, monitor, None))
+    }
 
+    fn from_parts(map: Mapper<'a>, monitor: Monitor<'a>, input: Option<Input>) -> AppleII<'a> {
         AppleII {
-            cpu: Cpu6502::new(map),
-            monitor: Monitor::new(sdl_video),
-            input: Input::new(sdl_events, sdl_keyboard),
+            cpu: Cpu::new(map.model.cpu(), map),
+            monitor: monitor,
+            input: input,
             paused: false,
+            nmi_line: false,
+            held_open_apple: None,
         }
     }
 
+    /* The configured character rom, or the built in set for the model. */
+    fn char_rom(config: &MachineConfig) -> Result<CharRom, ConfigError> {
+        match config.char_rom {
+            Some(ref path) => CharRom::from_file(path),
+            None => Ok(CharRom::builtin(config.model())),
+        }
+    }
+
+    fn build_mapper(rom: Vec<u8>, config: &MachineConfig) -> Result<Mapper<'a>, ConfigError> {
+        let model = config.model();
+        /* language cards shadow the $D000-$FFFF part of the rom */
+        let mut lang_rom = [0x00; ROM_SIZE];
+        lang_rom.copy_from_slice(&rom[rom.len() - ROM_SIZE..]);
+
+        let mut map = Mapper::new(model, rom, config.ram() * 0x400, config.ram_init());
+        if model.has_builtin_language_card() {
+            let ram_init = map.ram_init;
+            map.add_card(LanguageCard::with_aux_bank(lang_rom, ram_init), 0);
+        }
+        if !model.has_slots() {
+            /* the //c's serial ports sit where cards would in slots 1 and 2 */
+            map.add_card(SerialPort::new(1), 1);
+            map.add_card(SerialPort::new(2), 2);
+            if config.slots[6].is_none() {
+                map.add_card(DiskII::new(), 6);
+            }
+        }
+        for (slot, card) in config.slots.iter().enumerate() {
+            if let Some(ref card) = *card {
+                info!("Adding card {} in slot {}", card.kind.name(), slot);
+                try!(AppleII::add_card(&mut map, lang_rom, card, slot));
+            }
+        }
+        Ok(map)
+    }
+
+    fn add_card(map: &mut Mapper<'a>,
+                rom: [u8; ROM_SIZE],
+                card: &CardConfig,
+                slot: usize)
+                -> Result<(), ConfigError> {
+        let ram_init = map.ram_init;
+        match card.kind {
+            CardKind::LanguageCard => map.add_card(LanguageCard::new(rom, ram_init), slot),
+            CardKind::Saturn128K => map.add_card(Saturn128K::new(rom, ram_init), slot),
+            CardKind::DiskII => {
+                let mut dc = DiskII::new();
+                if let Some(path) = card.param("drive1") {
+                    dc.set_first_disk(try!(open_file(path)));
+                }
+                if let Some(path) = card.param("drive2") {
+                    dc.set_second_disk(try!(open_file(path)));
+                }
+                map.add_card(dc, slot);
+            }
+            CardKind::SoftCard => map.add_card(SoftCard::new(slot), slot),
+            CardKind::Videoterm => {
+                let rom = try!(read_file(card.param("rom").unwrap(), videoterm::ROM_SIZE));
+                let char_rom = try!(read_file(card.param("charrom").unwrap(),
+                                              videoterm::CHAR_ROM_SIZE));
+                map.add_card(Videoterm::new(rom, char_rom), slot);
+            }
+        }
+        Ok(())
+    }
+
     pub fn run(&mut self) {
         'runloop: loop {
             let begin = Instant::now();
 
-            for input in self.input.keyboard_inputs()
-            {
+            /* collected first, since handling them needs all of self */
+            let inputs: Vec<KeyboardInput> = match self.input {
+                Some(ref mut input) => input.keyboard_inputs().collect(),
+                None => Vec::new(),
+            };
+            for input in inputs {
                 match input {
                     KeyboardInput::Quit => break 'runloop,
-                    KeyboardInput::Reset => if !self.paused { self.cpu.reset() },
-                    KeyboardInput::Key(val) => if !self.paused { self.cpu.memory.set_key(val) },
+                    KeyboardInput::Reset => if !self.paused { self.reset() },
+                    KeyboardInput::ForceReboot => if !self.paused { self.force_reboot() },
+                    KeyboardInput::PowerCycle => self.power_cycle(),
+                    KeyboardInput::Key(val) => if !self.paused { self.cpu.memory_mut().set_key(val) },
+                    KeyboardInput::Button(button, pressed) => self.set_button(button, pressed),
+                    KeyboardInput::Paddle(paddle, val) => {
+                        self.cpu.memory_mut().set_paddle(paddle, val)
+                    }
+                    KeyboardInput::MouseMove(dx, dy) => self.cpu.memory_mut().move_mouse(dx, dy),
+                    KeyboardInput::MouseButton(pressed) => self.cpu.memory_mut().set_mouse_button(pressed),
                     KeyboardInput::Pause => self.paused = !self.paused,
+                    KeyboardInput::CycleVideo => {
+                        self.monitor.cycle_mode();
+                        info!("Video mode {}", self.monitor.mode().name());
+                    }
+                    KeyboardInput::Screenshot(window, as_shown) => {
+                        self.save_screenshot(window, as_shown)
+                    }
+                    KeyboardInput::Record(raw) => self.toggle_recording(raw),
                 }
             }
 
-            self.monitor.update_window(&self.cpu.memory, self.cpu.cycles);
-
-            if !self.paused
-            {
-                /* 16666 clocks per 1/60 seconds */
-                self.cpu.run(16666).expect("AAAAA CPU DIED");
+            if self.paused {
+                /* the beam isn't moving, so keep showing the last frame */
+                self.monitor.present(self.cpu.memory());
+            } else {
+                /* a frame of video per 1/60 second */
+                self.run_frame();
             }
 
             let elapsed = begin.elapsed();
@@ -80,5 +207,367 @@ impl<'a> AppleII<'a> {
                 thread::sleep(fps60 - elapsed);
             }
         }
+        self.stop_recording();
+    }
+
+    /* Runs for as long as the beam takes to draw a frame, which the
+     * monitor shows as the beam finishes it.
+     */
+    fn run_frame(&mut self) {
+        self.run_cycles(scanner::CYCLES_PER_FRAME);
+        if let Some(pressed) = self.held_open_apple.take() {
+            self.cpu.memory_mut().set_button(0, pressed);
+        }
+    }
+
+    /* Runs flat out with no input, for a headless machine. */
+    pub fn run_frames(&mut self, frames: u64) {
+        for _ in 0..frames {
+            self.run_frame();
+        }
+    }
+
+    /* Saves the screen as a PNG, see Monitor::screenshot. */
+    pub fn screenshot(&mut self, path: &str, size: ShotSize, as_shown: bool) -> io::Result<()> {
+        self.monitor.screenshot(path, size, as_shown)
+    }
+
+    /* Saves the screen to the first free screenshot-N.png, at the
+     * window's size or the Apple's, in the current video mode or in
+     * plain colors.
+     */
+    fn save_screenshot(&mut self, window: bool, as_shown: bool) {
+        let path = match unused_path("screenshot", "png") {
+            Some(path) => path,
+            None => return,
+        };
+        let size = if window { ShotSize::Window } else { ShotSize::Native };
+        match self.screenshot(&path, size, as_shown) {
+            Ok(()) => info!("Saved screenshot {}", path),
+            Err(err) => warn!("Could not save screenshot {}: {}", path, err),
+        }
+    }
+
+    /* Records every frame from here on, see Recorder. */
+    pub fn start_recording(&mut self, path: &str, format: Format) -> io::Result<()> {
+        self.stop_recording();
+        let recorder = try!(Recorder::new(path, format));
+        self.monitor.record(recorder);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.monitor.stop_recording() {
+            match recorder.finish() {
+                Ok(()) => info!("Recording finished"),
+                Err(err) => warn!("Could not finish recording: {}", err),
+            }
+        }
+    }
+
+    /* Starts recording to the first free recording-N file, as an
+     * animated PNG or a raw stream, or stops the recording under way.
+     */
+    fn toggle_recording(&mut self, raw: bool) {
+        if self.monitor.recording() {
+            self.stop_recording();
+            return;
+        }
+        let format = if raw { Format::Y4m } else { Format::Apng };
+        let path = match unused_path("recording", format.extension()) {
+            Some(path) => path,
+            None => return,
+        };
+        match self.start_recording(&path, format) {
+            Ok(()) => info!("Recording to {}", path),
+            Err(err) => warn!("Could not record to {}: {}", path, err),
+        }
+    }
+
+    /* The RESET key: RAM and most card state survive. */
+    pub fn reset(&mut self) {
+        self.cpu.memory_mut().reset();
+        self.cpu.reset();
+    }
+
+    /* Turning the machine off and on again. */
+    pub fn power_cycle(&mut self) {
+        self.cpu.memory_mut().power_on();
+        self.nmi_line = false;
+        self.cpu.reset();
+    }
+
+    /* Ctrl-Open-Apple-Reset. The //e and //c firmware checks open apple
+     * in its reset handler and reboots from scratch if it is down, so
+     * hold it for a frame. Other models just get a reset.
+     */
+    pub fn force_reboot(&mut self) {
+        if self.cpu.memory().model.has_aux_memory() {
+            if self.held_open_apple.is_none() {
+                self.held_open_apple = Some(self.cpu.memory().game_io.buttons[0]);
+            }
+            self.cpu.memory_mut().set_button(0, true);
+        }
+        self.reset();
+    }
+
+    /* While a forced reboot holds open apple, changes to it are kept
+     * for when the hold ends.
+     */
+    fn set_button(&mut self, button: usize, pressed: bool) {
+        match self.held_open_apple {
+            Some(ref mut held) if button == 0 => *held = pressed,
+            _ => self.cpu.memory_mut().set_button(button, pressed),
+        }
+    }
+
+    /* Steps the cpu an instruction at a time so cards see
+     * elapsed time and can interrupt between instructions.
+     */
+    fn run_cycles(&mut self, cycles: u64) {
+        let end = self.cpu.cycles() + cycles;
+        while self.cpu.cycles() < end {
+            let begin = self.cpu.cycles();
+            /* the beam catches up first, so it sees switches as they were
+             * up to this instruction
+             */
+            self.monitor.scan_to(self.cpu.memory(), begin);
+            self.cpu.memory_mut().start_instruction(begin);
+            match self.cpu.memory().bus_master() {
+                Some(slot) => {
+                    let slice = cmp::min(end - begin, BUS_MASTER_SLICE);
+                    /* the 6502 is halted, but time still passes */
+                    let taken = self.cpu.memory_mut().run_bus_master(slot, slice);
+                    self.cpu.add_cycles(taken);
+                }
+                None => self.cpu.step(),
+            }
+            /* then up to each display switch the instruction flipped */
+            for (cycle, screen) in self.cpu.memory_mut().take_screen_changes() {
+                self.monitor.scan_before_switch(self.cpu.memory(), &screen, cycle);
+            }
+            let elapsed = self.cpu.cycles() - begin;
+            self.cpu.memory_mut().tick(elapsed);
+
+            let nmi = self.cpu.memory().nmi();
+            if nmi && !self.nmi_line {
+                self.cpu.nmi();
+            }
+            self.nmi_line = nmi;
+
+            if self.cpu.memory().irq() {
+                self.cpu.irq();
+            }
+        }
+    }
+}
+
+/* The first of name-1.ext, name-2.ext and so on that isn't taken. */
+fn unused_path(name: &str, extension: &str) -> Option<String> {
+    (1..).map(|n| format!("{}-{}.{}", name, n, extension)).find(|path| !Path::new(path).exists())
+}
+
+fn open_file(path: &str) -> Result<fs::File, ConfigError> {
+    fs::File::open(path).map_err(|err| ConfigError::Io(path.to_string(), err))
+}
+
+fn read_file(path: &str, size: usize) -> Result<Vec<u8>, ConfigError> {
+    let mut data = Vec::new();
+    try!(try!(open_file(path))
+        .read_to_end(&mut data)
+        .map_err(|err| ConfigError::Io(path.to_string(), err)));
+    if data.len() != size {
+        return Err(ConfigError::FileSize(path.to_string(), size, data.len()));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{RAM_SIZE, ScreenState};
+    use model::Model;
+    use r6502::memory::Memory;
+    use ram_init::RamInit;
+    use video_output::Headless;
+
+    use std::env;
+    use std::io::Cursor;
+
+    /* An enhanced //e on a blank rom, which sits in a BRK loop at $0000. */
+    fn machine<'a>(output: Headless) -> AppleII<'a> {
+        configured(MachineConfig::new(), output)
+    }
+
+    fn configured<'a>(mut config: MachineConfig, output: Headless) -> AppleII<'a> {
+        config.model = Some(Model::AppleIIeEnhanced);
+        let palette = Palette::preset("emulator").unwrap();
+        AppleII::headless(vec![0; 0x4000], &config, Box::new(output), RenderMode::Simple, palette)
+            .unwrap()
+    }
+
+    fn open_apple(apple: &AppleII) -> bool {
+        apple.cpu.memory().game_io.buttons[0]
+    }
+
+    #[test]
+    fn force_reboot_releases_open_apple() {
+        let mut apple = machine(Headless::new());
+        apple.force_reboot();
+        assert!(open_apple(&apple));
+        apple.run_frames(1);
+        assert!(!open_apple(&apple));
+    }
+
+    #[test]
+    fn force_reboot_keeps_a_held_open_apple() {
+        let mut apple = machine(Headless::new());
+        apple.set_button(0, true);
+        apple.force_reboot();
+        apple.run_frames(1);
+        assert!(open_apple(&apple));
+    }
+
+    #[test]
+    fn open_apple_changes_during_a_forced_reboot_are_kept() {
+        let mut apple = machine(Headless::new());
+        apple.force_reboot();
+        apple.set_button(0, true);
+        assert!(open_apple(&apple));
+        apple.run_frames(1);
+        assert!(open_apple(&apple));
+
+        apple.force_reboot();
+        apple.set_button(0, false);
+        assert!(open_apple(&apple));
+        apple.run_frames(1);
+        assert!(!open_apple(&apple));
+    }
+
+    /* Reads on from slot 6's drive to the next address field, and
+     * gives the track it is for.
+     */
+    fn disk_track(apple: &mut AppleII) -> u8 {
+        let memory = apple.cpu.memory_mut();
+        let mut last = [0; 3];
+        while last != [0xD5, 0xAA, 0x96] {
+            last = [last[1], last[2], memory.read_without_mm(0xC0EC)];
+        }
+        let mut field = [0; 4];
+        for byte in field.iter_mut() {
+            *byte = memory.read_without_mm(0xC0EC);
+        }
+        /* after the volume, odd bits then even */
+        (field[2] << 1 | 1) & field[3]
+    }
+
+    #[test]
+    fn power_cycle_starts_over() {
+        let mut config = MachineConfig::new();
+        config.ram_init = Some(RamInit::Random(1));
+        let mut apple = configured(config, Headless::new());
+        let mut disk = DiskII::new();
+        disk.set_first_disk(Cursor::new(vec![0; 35 * 16 * 256]));
+        apple.cpu.memory_mut().add_card(disk, 6);
+
+        let mut main = vec![0; RAM_SIZE];
+        RamInit::Random(1).fill(&mut main, 0);
+        let mut aux = vec![0; RAM_SIZE];
+        RamInit::Random(1).fill(&mut aux, 1);
+        assert!(main != aux);
+        assert_eq!(apple.cpu.memory().ram[..], main[..]);
+        assert_eq!(apple.cpu.memory().aux_ram, aux);
+
+        {
+            let memory = apple.cpu.memory_mut();
+            memory.ram[0x300] ^= 0xFF;
+            memory.aux_ram[0x300] ^= 0xFF;
+            /* each phase in turn walks the head out a half track */
+            for phase in 1..9 {
+                let switch = 0xC0E0 + (phase % 4) * 2;
+                memory.read_without_mm(switch + 1);
+                memory.read_without_mm(switch);
+            }
+        }
+        assert_eq!(disk_track(&mut apple), 4);
+
+        /* a reset leaves RAM alone */
+        apple.reset();
+        assert!(apple.cpu.memory().ram[..] != main[..]);
+        assert!(apple.cpu.memory().aux_ram != aux);
+
+        {
+            /* set after the reset, which clears some of these itself on a //e */
+            let memory = apple.cpu.memory_mut();
+            memory.screen.graphics = true;
+            memory.screen.col80 = true;
+            memory.mmu.ramrd = true;
+            memory.mmu.altzp = true;
+            memory.annunciators[2] = true;
+        }
+        apple.power_cycle();
+        {
+            let memory = apple.cpu.memory();
+            assert_eq!(memory.ram[..], main[..]);
+            assert_eq!(memory.aux_ram, aux);
+            assert!(memory.screen == ScreenState::new());
+            assert!(!memory.mmu.ramrd && !memory.mmu.altzp);
+            assert_eq!(memory.annunciators, [false; 4]);
+        }
+        assert_eq!(disk_track(&mut apple), 0);
+    }
+
+    #[test]
+    fn headless_shows_the_text_page() {
+        let output = Headless::new();
+        let shown = output.shown();
+        let mut apple = machine(output);
+        {
+            /* blank text page 1 but for an inverse space at the top left */
+            let ram = &mut apple.cpu.memory_mut().ram;
+            for byte in ram[0x400..0x800].iter_mut() {
+                *byte = 0xA0;
+            }
+            ram[0x400] = 0x20;
+        }
+        apple.run_frames(2);
+
+        let shown = shown.borrow();
+        assert_eq!(shown.width, FRAME_WIDTH);
+        assert_eq!(shown.height, FRAME_HEIGHT);
+        let lit = shown.pixel(0, 0);
+        let dark = (0x00, 0x00, 0x00);
+        assert!(lit != dark);
+        /* seven dots at double width, eight lines high */
+        assert_eq!(shown.pixel(13, 7), lit);
+        assert_eq!(shown.pixel(14, 0), dark);
+        assert_eq!(shown.pixel(0, 8), dark);
+        assert_eq!(shown.pixel(FRAME_WIDTH - 1, FRAME_HEIGHT - 1), dark);
+    }
+
+    #[test]
+    fn screenshot_sizes() {
+        let mut apple = machine(Headless::new());
+        apple.run_frames(1);
+        let path = env::temp_dir().join("appleii-screenshot-test.png");
+        let path = path.to_str().unwrap();
+
+        /* the PNG's width and height sit at 16 and 20, after the IHDR tag */
+        let size = |path: &str| {
+            let mut data = Vec::new();
+            fs::File::open(path).unwrap().read_to_end(&mut data).unwrap();
+            let word = |at: usize| {
+                (data[at] as usize) << 24 | (data[at + 1] as usize) << 16 |
+                (data[at + 2] as usize) << 8 | data[at + 3] as usize
+            };
+            (word(16), word(20))
+        };
+        for &as_shown in &[false, true] {
+            apple.screenshot(path, ShotSize::Native, as_shown).unwrap();
+            assert_eq!(size(path), (FRAME_WIDTH / 2, FRAME_HEIGHT));
+            apple.screenshot(path, ShotSize::Window, as_shown).unwrap();
+            assert_eq!(size(path), (FRAME_WIDTH, FRAME_HEIGHT * 2));
+        }
+        let _ = fs::remove_file(path);
     }
 }
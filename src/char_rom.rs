@@ -0,0 +1,404 @@
+use config::ConfigError;
+use model::Model;
+
+use std::fs;
+use std::io::Read;
+use std::ops::Range;
+
+/* The character generator: what each screen byte looks like in text
+ * mode, as eight rows of seven dots with bit 0 leftmost.
+ *
+ * A rom is 256 characters of 8 rows each, addressed by the screen byte
+ * then the row: 2K for one character set, or 4K for a //e video rom
+ * with the alternate set in the upper half. The II's 512 byte rom of 64
+ * characters is taken too. Dumps differ in whether dots are stored
+ * active low and which end of the byte is leftmost, and in whether the
+ * inverse and flashing characters are stored inverted or left for the
+ * video circuitry to invert, so all of that is worked out from the
+ * spaces and the letter L.
+ *
+ * Without a rom the built in set is used, which has no MouseText.
+ */
+
+const CHARS_PER_SET: usize = 0x100;
+const SET_SIZE: usize = CHARS_PER_SET * 8;
+
+pub struct CharRom {
+    /* the primary set then the //e's alternate one, inverse characters
+     * stored inverted and flashing ones as they look half the time
+     */
+    rows: Vec<u8>,
+}
+
+impl CharRom {
+    pub fn builtin(model: Model) -> CharRom {
+        let mut set = vec![0; SET_SIZE];
+        for character in 0..CHARS_PER_SET {
+            let glyph = match character {
+                0xE0...0xFF if model.has_lowercase() => 0x40 + (character & 0x1F),
+                _ => character & 0x3F,
+            };
+            let rows = &GLYPHS[glyph * 8..(glyph + 1) * 8];
+            set[character * 8..(character + 1) * 8].copy_from_slice(rows);
+        }
+        invert(&mut set, 0x00..0x40);
+
+        let alternate = alternate_set(&set);
+        set.extend_from_slice(&alternate);
+        CharRom { rows: set }
+    }
+
+    pub fn from_file(path: &str) -> Result<CharRom, ConfigError> {
+        let mut data = Vec::new();
+        try!(fs::File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .map_err(|err| ConfigError::Io(path.to_string(), err)));
+        CharRom::from_bytes(&data).map_err(|msg| ConfigError::CharRom(path.to_string(), msg))
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<CharRom, &'static str> {
+        let data = match data.len() {
+            /* the II's 64 characters repeat through the screen byte's top bits */
+            0x200 => data.iter().cycle().take(SET_SIZE).cloned().collect::<Vec<u8>>(),
+            0x800 | 0x1000 => data.to_vec(),
+            _ => return Err("expected 512, 2048 or 4096 bytes"),
+        };
+
+        let space = &data[0xA0 * 8..0xA1 * 8];
+        let active_low = if space.iter().all(|row| row & 0x7F == 0) {
+            false
+        } else if space.iter().all(|row| row & 0x7F == 0x7F) {
+            true
+        } else {
+            return Err("character $A0 is not a space");
+        };
+        let mut rows: Vec<u8> = data.iter()
+            .map(|row| if active_low { !row & 0x7F } else { row & 0x7F })
+            .collect();
+
+        /* the upright of the L is a single dot near the left */
+        let upright: Vec<u8> = rows[0xCC * 8..0xCD * 8]
+            .iter()
+            .cloned()
+            .filter(|row| row.count_ones() == 1)
+            .collect();
+        let mirrored = match upright.first() {
+            Some(&dot) if upright.len() >= 4 && upright.iter().all(|row| *row == dot) => {
+                dot > 1 << 3
+            }
+            _ => return Err("character $CC is not an L"),
+        };
+        if mirrored {
+            for row in rows.iter_mut() {
+                *row = mirror(*row);
+            }
+        }
+
+        /* inverse characters are stored as they look, so an unlit inverse
+         * space means the video circuitry does the inverting
+         */
+        let hardware_inverse = !try!(lit(&rows, 0x20));
+        if hardware_inverse {
+            invert(&mut rows[..SET_SIZE], 0x00..0x40);
+        }
+        /* and flashing ones in their normal half */
+        if try!(lit(&rows, 0x60)) {
+            invert(&mut rows[..SET_SIZE], 0x40..0x80);
+        }
+
+        if rows.len() == SET_SIZE {
+            let alternate = alternate_set(&rows);
+            rows.extend_from_slice(&alternate);
+        } else if hardware_inverse {
+            /* MouseText and the rest of $40-$7F are taken as stored */
+            invert(&mut rows[SET_SIZE..], 0x00..0x40);
+        }
+        Ok(CharRom { rows: rows })
+    }
+
+    /* One row of dots of a screen byte, `flash` being set while
+     * flashing characters are in their inverse half.
+     */
+    pub fn row(&self, character: u8, row: usize, altcharset: bool, flash: bool) -> u8 {
+        let set = if altcharset { CHARS_PER_SET } else { 0 };
+        let dots = self.rows[(set + character as usize) * 8 + row];
+        if flash && !altcharset && character & 0xC0 == 0x40 {
+            !dots & 0x7F
+        } else {
+            dots
+        }
+    }
+}
+
+/* Whether a character that should be a space is all lit or all dark. */
+fn lit(rows: &[u8], character: usize) -> Result<bool, &'static str> {
+    let space = &rows[character * 8..(character + 1) * 8];
+    if space.iter().all(|row| *row == 0) {
+        Ok(false)
+    } else if space.iter().all(|row| *row == 0x7F) {
+        Ok(true)
+    } else {
+        Err("characters $20 and $60 are not spaces")
+    }
+}
+
+/* Seven dots the other way round. */
+fn mirror(row: u8) -> u8 {
+    (0..7).filter(|dot| row & (1 << dot) != 0).fold(0, |mirrored, dot| mirrored | (0x40 >> dot))
+}
+
+fn invert(set: &mut [u8], characters: Range<usize>) {
+    for row in set[characters.start * 8..characters.end * 8].iter_mut() {
+        *row = !*row & 0x7F;
+    }
+}
+
+/* The //e's alternate set for a rom that only has the primary one:
+ * inverse where the primary set flashes, lowercase included, as on
+ * the original //e.
+ */
+fn alternate_set(primary: &[u8]) -> Vec<u8> {
+    let mut set = primary.to_vec();
+    set[0x40 * 8..0x80 * 8].copy_from_slice(&primary[0xC0 * 8..0x100 * 8]);
+    invert(&mut set, 0x40..0x80);
+    set
+}
+
+/* Uppercase and symbols by their low six bits, then the //e's lowercase
+ * from $E0.
+ */
+static GLYPHS: [u8; 0x60 * 8] = [
+    0x1C, 0x22, 0x2A, 0x3A, 0x1A, 0x02, 0x3C, 0x00, /* @ */
+    0x08, 0x14, 0x22, 0x22, 0x3E, 0x22, 0x22, 0x00, /* A */
+    0x1E, 0x22, 0x22, 0x1E, 0x22, 0x22, 0x1E, 0x00, /* B */
+    0x1C, 0x22, 0x02, 0x02, 0x02, 0x22, 0x1C, 0x00, /* C */
+    0x1E, 0x22, 0x22, 0x22, 0x22, 0x22, 0x1E, 0x00, /* D */
+    0x3E, 0x02, 0x02, 0x1E, 0x02, 0x02, 0x3E, 0x00, /* E */
+    0x3E, 0x02, 0x02, 0x1E, 0x02, 0x02, 0x02, 0x00, /* F */
+    0x3C, 0x02, 0x02, 0x02, 0x32, 0x22, 0x3C, 0x00, /* G */
+    0x22, 0x22, 0x22, 0x3E, 0x22, 0x22, 0x22, 0x00, /* H */
+    0x3E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x3E, 0x00, /* I */
+    0x20, 0x20, 0x20, 0x20, 0x20, 0x22, 0x1C, 0x00, /* J */
+    0x22, 0x12, 0x0A, 0x06, 0x0A, 0x12, 0x22, 0x00, /* K */
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x3E, 0x00, /* L */
+    0x22, 0x36, 0x2A, 0x22, 0x22, 0x22, 0x22, 0x00, /* M */
+    0x22, 0x22, 0x26, 0x2A, 0x32, 0x22, 0x22, 0x00, /* N */
+    0x1C, 0x22, 0x22, 0x22, 0x22, 0x22, 0x1C, 0x00, /* O */
+    0x1E, 0x22, 0x22, 0x1E, 0x02, 0x02, 0x02, 0x00, /* P */
+    0x1C, 0x22, 0x22, 0x22, 0x2A, 0x12, 0x2C, 0x00, /* Q */
+    0x1E, 0x22, 0x22, 0x1E, 0x0A, 0x12, 0x22, 0x00, /* R */
+    0x1C, 0x22, 0x02, 0x1C, 0x20, 0x22, 0x1C, 0x00, /* S */
+    0x3E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x00, /* T */
+    0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x1C, 0x00, /* U */
+    0x22, 0x22, 0x22, 0x22, 0x22, 0x14, 0x08, 0x00, /* V */
+    0x22, 0x22, 0x22, 0x22, 0x2A, 0x36, 0x22, 0x00, /* W */
+    0x22, 0x22, 0x14, 0x08, 0x14, 0x22, 0x22, 0x00, /* X */
+    0x22, 0x22, 0x14, 0x08, 0x08, 0x08, 0x08, 0x00, /* Y */
+    0x3E, 0x20, 0x10, 0x08, 0x04, 0x02, 0x3E, 0x00, /* Z */
+    0x3E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x3E, 0x00, /* [ */
+    0x00, 0x02, 0x04, 0x08, 0x10, 0x20, 0x00, 0x00, /* \ */
+    0x3E, 0x30, 0x30, 0x30, 0x30, 0x30, 0x3E, 0x00, /* ] */
+    0x00, 0x00, 0x08, 0x14, 0x22, 0x00, 0x00, 0x00, /* ^ */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3E, 0x00, /* _ */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* space */
+    0x08, 0x08, 0x08, 0x08, 0x08, 0x00, 0x08, 0x00, /* ! */
+    0x14, 0x14, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, /* " */
+    0x14, 0x14, 0x3E, 0x14, 0x3E, 0x14, 0x14, 0x00, /* # */
+    0x08, 0x3C, 0x0A, 0x1C, 0x28, 0x1E, 0x08, 0x00, /* $ */
+    0x06, 0x26, 0x10, 0x08, 0x04, 0x32, 0x30, 0x00, /* % */
+    0x04, 0x0A, 0x0A, 0x04, 0x2A, 0x12, 0x2C, 0x00, /* & */
+    0x08, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, /* ' */
+    0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08, 0x00, /* ( */
+    0x08, 0x10, 0x20, 0x20, 0x20, 0x10, 0x08, 0x00, /* ) */
+    0x08, 0x2A, 0x1C, 0x08, 0x1C, 0x2A, 0x08, 0x00, /* * */
+    0x00, 0x08, 0x08, 0x3E, 0x08, 0x08, 0x00, 0x00, /* + */
+    0x00, 0x00, 0x00, 0x00, 0x08, 0x08, 0x04, 0x00, /* , */
+    0x00, 0x00, 0x00, 0x3E, 0x00, 0x00, 0x00, 0x00, /* - */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, /* . */
+    0x00, 0x20, 0x10, 0x08, 0x04, 0x02, 0x00, 0x00, /* / */
+    0x1C, 0x22, 0x32, 0x2A, 0x26, 0x22, 0x1C, 0x00, /* 0 */
+    0x08, 0x0C, 0x08, 0x08, 0x08, 0x08, 0x1C, 0x00, /* 1 */
+    0x1C, 0x22, 0x20, 0x18, 0x04, 0x02, 0x3E, 0x00, /* 2 */
+    0x3E, 0x20, 0x10, 0x18, 0x20, 0x22, 0x1C, 0x00, /* 3 */
+    0x10, 0x18, 0x14, 0x12, 0x3E, 0x10, 0x10, 0x00, /* 4 */
+    0x3E, 0x02, 0x1E, 0x20, 0x20, 0x22, 0x1C, 0x00, /* 5 */
+    0x38, 0x04, 0x02, 0x1E, 0x22, 0x22, 0x1C, 0x00, /* 6 */
+    0x3E, 0x20, 0x10, 0x08, 0x04, 0x04, 0x04, 0x00, /* 7 */
+    0x1C, 0x22, 0x22, 0x1C, 0x22, 0x22, 0x1C, 0x00, /* 8 */
+    0x1C, 0x22, 0x22, 0x3C, 0x20, 0x10, 0x0E, 0x00, /* 9 */
+    0x00, 0x00, 0x08, 0x00, 0x08, 0x00, 0x00, 0x00, /* : */
+    0x00, 0x00, 0x08, 0x00, 0x08, 0x08, 0x04, 0x00, /* ; */
+    0x10, 0x08, 0x04, 0x02, 0x04, 0x08, 0x10, 0x00, /* < */
+    0x00, 0x00, 0x3E, 0x00, 0x3E, 0x00, 0x00, 0x00, /* = */
+    0x04, 0x08, 0x10, 0x20, 0x10, 0x08, 0x04, 0x00, /* > */
+    0x1C, 0x22, 0x10, 0x08, 0x08, 0x00, 0x08, 0x00, /* ? */
+    0x02, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, /* ` */
+    0x00, 0x00, 0x1C, 0x20, 0x3C, 0x22, 0x3C, 0x00, /* a */
+    0x02, 0x02, 0x1E, 0x22, 0x22, 0x22, 0x1E, 0x00, /* b */
+    0x00, 0x00, 0x3C, 0x02, 0x02, 0x02, 0x3C, 0x00, /* c */
+    0x20, 0x20, 0x3C, 0x22, 0x22, 0x22, 0x3C, 0x00, /* d */
+    0x00, 0x00, 0x1C, 0x22, 0x3E, 0x02, 0x3C, 0x00, /* e */
+    0x18, 0x24, 0x04, 0x1E, 0x04, 0x04, 0x04, 0x00, /* f */
+    0x00, 0x00, 0x1C, 0x22, 0x22, 0x3C, 0x20, 0x1C, /* g */
+    0x02, 0x02, 0x1E, 0x22, 0x22, 0x22, 0x22, 0x00, /* h */
+    0x08, 0x00, 0x0C, 0x08, 0x08, 0x08, 0x1C, 0x00, /* i */
+    0x10, 0x00, 0x18, 0x10, 0x10, 0x10, 0x12, 0x0C, /* j */
+    0x02, 0x02, 0x22, 0x12, 0x0E, 0x12, 0x22, 0x00, /* k */
+    0x0C, 0x08, 0x08, 0x08, 0x08, 0x08, 0x1C, 0x00, /* l */
+    0x00, 0x00, 0x36, 0x2A, 0x2A, 0x2A, 0x22, 0x00, /* m */
+    0x00, 0x00, 0x1E, 0x22, 0x22, 0x22, 0x22, 0x00, /* n */
+    0x00, 0x00, 0x1C, 0x22, 0x22, 0x22, 0x1C, 0x00, /* o */
+    0x00, 0x00, 0x1E, 0x22, 0x22, 0x1E, 0x02, 0x02, /* p */
+    0x00, 0x00, 0x3C, 0x22, 0x22, 0x3C, 0x20, 0x20, /* q */
+    0x00, 0x00, 0x3A, 0x06, 0x02, 0x02, 0x02, 0x00, /* r */
+    0x00, 0x00, 0x3C, 0x02, 0x1C, 0x20, 0x1E, 0x00, /* s */
+    0x04, 0x04, 0x1E, 0x04, 0x04, 0x24, 0x18, 0x00, /* t */
+    0x00, 0x00, 0x22, 0x22, 0x22, 0x32, 0x2C, 0x00, /* u */
+    0x00, 0x00, 0x22, 0x22, 0x22, 0x14, 0x08, 0x00, /* v */
+    0x00, 0x00, 0x22, 0x22, 0x2A, 0x2A, 0x36, 0x00, /* w */
+    0x00, 0x00, 0x22, 0x14, 0x08, 0x14, 0x22, 0x00, /* x */
+    0x00, 0x00, 0x22, 0x22, 0x22, 0x3C, 0x20, 0x1C, /* y */
+    0x00, 0x00, 0x3E, 0x10, 0x08, 0x04, 0x3E, 0x00, /* z */
+    0x18, 0x04, 0x04, 0x02, 0x04, 0x04, 0x18, 0x00, /* { */
+    0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x00, /* | */
+    0x06, 0x08, 0x08, 0x10, 0x08, 0x08, 0x06, 0x00, /* } */
+    0x2C, 0x1A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* ~ */
+    0x2A, 0x14, 0x2A, 0x14, 0x2A, 0x14, 0x2A, 0x00, /* DEL */
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* A 2K II+ style rom as its chip holds it: every character stored
+     * as its plain glyph, leaving inverse and flashing to the video
+     * circuitry.
+     */
+    fn plain_rom() -> Vec<u8> {
+        (0..SET_SIZE).map(|at| GLYPHS[((at / 8) & 0x3F) * 8 + at % 8]).collect()
+    }
+
+    fn load(data: &[u8]) -> CharRom {
+        match CharRom::from_bytes(data) {
+            Ok(rom) => rom,
+            Err(msg) => panic!("rom did not load: {}", msg),
+        }
+    }
+
+    fn assert_same(rom: &CharRom, expected: &CharRom) {
+        for character in 0..CHARS_PER_SET {
+            for row in 0..8 {
+                for &altcharset in &[false, true] {
+                    for &flash in &[false, true] {
+                        assert_eq!(rom.row(character as u8, row, altcharset, flash),
+                                   expected.row(character as u8, row, altcharset, flash),
+                                   "${:02X} row {} altcharset {} flash {}",
+                                   character,
+                                   row,
+                                   altcharset,
+                                   flash);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn plain_rom_sizes() {
+        let expected = CharRom::builtin(Model::AppleIIPlus);
+        let rom = plain_rom();
+        assert_same(&load(&rom), &expected);
+        assert_same(&load(&rom[..0x200]), &expected);
+    }
+
+    #[test]
+    fn active_low_and_mirrored() {
+        let expected = CharRom::builtin(Model::AppleIIPlus);
+        let rom = plain_rom();
+        let active_low: Vec<u8> = rom.iter().map(|row| !row).collect();
+        let mirrored: Vec<u8> = rom.iter().map(|row| mirror(*row)).collect();
+        let both: Vec<u8> = mirrored.iter().map(|row| !row).collect();
+        assert_same(&load(&active_low), &expected);
+        assert_same(&load(&mirrored), &expected);
+        assert_same(&load(&both), &expected);
+    }
+
+    #[test]
+    fn pre_inverted() {
+        let mut rom = plain_rom();
+        /* inverse characters as they look, flashing ones in their inverse half */
+        invert(&mut rom, 0x00..0x80);
+        assert_same(&load(&rom), &CharRom::builtin(Model::AppleIIPlus));
+    }
+
+    #[test]
+    fn alternate_set_from_a_4k_rom() {
+        let mut rom = plain_rom();
+        let mut alternate = plain_rom();
+        for row in alternate[0x41 * 8..0x42 * 8].iter_mut() {
+            *row = 0x55;
+        }
+        rom.extend_from_slice(&alternate);
+        let rom = load(&rom);
+
+        /* the letter A */
+        for (row, &a) in GLYPHS[8..16].iter().enumerate() {
+            /* MouseText and the like are taken as stored and never flash */
+            assert_eq!(rom.row(0x41, row, true, false), 0x55);
+            assert_eq!(rom.row(0x41, row, true, true), 0x55);
+            /* inverse is still done by the circuitry */
+            assert_eq!(rom.row(0x01, row, true, false), !a & 0x7F);
+            assert_eq!(rom.row(0xC1, row, true, false), a);
+            assert_eq!(rom.row(0x41, row, false, false), a);
+            assert_eq!(rom.row(0x41, row, false, true), !a & 0x7F);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(CharRom::from_bytes(&[0; 0x400]).err(),
+                   Some("expected 512, 2048 or 4096 bytes"));
+
+        let mut rom = plain_rom();
+        rom[0xA0 * 8] = 0x08;
+        assert_eq!(CharRom::from_bytes(&rom).err(), Some("character $A0 is not a space"));
+
+        let mut rom = plain_rom();
+        rom[0xCC * 8..0xCD * 8].copy_from_slice(&GLYPHS[0x0F * 8..0x10 * 8]);
+        assert_eq!(CharRom::from_bytes(&rom).err(), Some("character $CC is not an L"));
+
+        let mut rom = plain_rom();
+        rom[0x60 * 8] = 0x08;
+        assert_eq!(CharRom::from_bytes(&rom).err(),
+                   Some("characters $20 and $60 are not spaces"));
+    }
+
+    #[test]
+    fn flash_and_altcharset() {
+        let rom = CharRom::builtin(Model::AppleIIe);
+        let a = &GLYPHS[8..16];
+        let lower_a = &GLYPHS[0x41 * 8..0x42 * 8];
+        for row in 0..8 {
+            let inverse = !a[row] & 0x7F;
+            /* $00-$3F inverse, $40-$7F flashing, $80-$FF normal */
+            assert_eq!(rom.row(0x01, row, false, false), inverse);
+            assert_eq!(rom.row(0x01, row, false, true), inverse);
+            assert_eq!(rom.row(0x41, row, false, false), a[row]);
+            assert_eq!(rom.row(0x41, row, false, true), inverse);
+            assert_eq!(rom.row(0xC1, row, false, true), a[row]);
+            assert_eq!(rom.row(0xE1, row, false, false), lower_a[row]);
+
+            /* the alternate set has inverse in place of flashing, lowercase too */
+            assert_eq!(rom.row(0x41, row, true, false), inverse);
+            assert_eq!(rom.row(0x41, row, true, true), inverse);
+            assert_eq!(rom.row(0x61, row, true, true), !lower_a[row] & 0x7F);
+            assert_eq!(rom.row(0xC1, row, true, true), a[row]);
+        }
+
+        /* the II+ has no lowercase */
+        let rom = CharRom::builtin(Model::AppleIIPlus);
+        for row in 0..8 {
+            assert_eq!(rom.row(0xE1, row, false, false), GLYPHS[0x21 * 8 + row]);
+        }
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+
+use model::Model;
+use ram_init::RamInit;
+
+pub const NUM_SLOTS: usize = 8;
+
+/* Motherboard RAM sizes in K the II and II+ could be fitted with */
+pub const RAM_SIZES: [usize; 9] = [4, 8, 12, 16, 20, 24, 32, 36, 48];
+
+/* Machine configuration files are plain text, one setting per line:
+ *
+ *     # lines starting with '#' are comments
+ *     model = iiplus
+ *     rom = apple2plus.rom
+ *     charrom = lowercase.rom
+ *     ram = 48
+ *     ram_init = random:1234
+ *     slot0 = language
+ *     slot6 = disk2 drive1=dos33.dsk drive2=blank.dsk
+ *
+ * The same card specs can be given on the command line with
+ * `--slot 6=disk2,drive1=dos33.dsk`.
+ */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CardKind {
+    LanguageCard,
+    DiskII,
+    SoftCard,
+    Videoterm,
+    Saturn128K,
+}
+
+impl CardKind {
+    fn from_name(name: &str) -> Option<CardKind> {
+        match name {
+            "language" => Some(CardKind::LanguageCard),
+            "disk2" => Some(CardKind::DiskII),
+            "softcard" => Some(CardKind::SoftCard),
+            "videx" => Some(CardKind::Videoterm),
+            "saturn" => Some(CardKind::Saturn128K),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CardKind::LanguageCard => "language",
+            CardKind::DiskII => "disk2",
+            CardKind::SoftCard => "softcard",
+            CardKind::Videoterm => "videx",
+            CardKind::Saturn128K => "saturn",
+        }
+    }
+
+    /* Cards that replace the rom at $D000-$FFFF, which only slot 0 supports. */
+    fn is_language_card(&self) -> bool {
+        match *self {
+            CardKind::LanguageCard | CardKind::Saturn128K => true,
+            _ => false,
+        }
+    }
+
+    fn required_params(&self) -> &'static [&'static str] {
+        match *self {
+            CardKind::LanguageCard => &[],
+            CardKind::DiskII => &[],
+            CardKind::SoftCard => &[],
+            CardKind::Videoterm => &["rom", "charrom"],
+            CardKind::Saturn128K => &[],
+        }
+    }
+
+    fn optional_params(&self) -> &'static [&'static str] {
+        match *self {
+            CardKind::LanguageCard => &[],
+            CardKind::DiskII => &["drive1", "drive2"],
+            CardKind::SoftCard => &[],
+            CardKind::Videoterm => &[],
+            CardKind::Saturn128K => &[],
+        }
+    }
+
+    /* Parameters naming files that have to exist at startup. */
+    fn file_params(&self) -> &'static [&'static str] {
+        match *self {
+            CardKind::LanguageCard => &[],
+            CardKind::DiskII => &["drive1", "drive2"],
+            CardKind::SoftCard => &[],
+            CardKind::Videoterm => &["rom", "charrom"],
+            CardKind::Saturn128K => &[],
+        }
+    }
+}
+
+pub struct CardConfig {
+    pub kind: CardKind,
+    pub params: HashMap<String, String>,
+}
+
+impl CardConfig {
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params.get(key).map(|val| val.as_str())
+    }
+
+    /* Parses "card key=val key=val ..." where `sep` splits the fields. */
+    fn parse(spec: &str, sep: char) -> Result<CardConfig, ConfigError> {
+        let mut fields = spec.split(sep).map(|field| field.trim()).filter(|field| !field.is_empty());
+
+        let name = match fields.next() {
+            Some(name) => name,
+            None => return Err(ConfigError::Syntax(format!("missing card name in \"{}\"", spec))),
+        };
+        let kind = match CardKind::from_name(name) {
+            Some(kind) => kind,
+            None => return Err(ConfigError::UnknownCard(name.to_string())),
+        };
+
+        let mut params = HashMap::new();
+        for field in fields {
+            let mut kv = field.splitn(2, '=');
+            let key = kv.next().unwrap().trim();
+            let val = match kv.next() {
+                Some(val) => val.trim(),
+                None => {
+                    return Err(ConfigError::Syntax(format!("expected key=value, found \"{}\"",
+                                                           field)))
+                }
+            };
+            if params.insert(key.to_string(), val.to_string()).is_some() {
+                return Err(ConfigError::Syntax(format!("parameter \"{}\" given twice", key)));
+            }
+        }
+
+        Ok(CardConfig {
+            kind: kind,
+            params: params,
+        })
+    }
+}
+
+pub struct MachineConfig {
+    pub model: Option<Model>,
+    pub rom: Option<String>,
+    /* character generator rom, see CharRom */
+    pub char_rom: Option<String>,
+    /* motherboard RAM in K */
+    pub ram: Option<usize>,
+    pub ram_init: Option<RamInit>,
+    pub slots: [Option<CardConfig>; NUM_SLOTS],
+}
+
+impl MachineConfig {
+    pub fn new() -> MachineConfig {
+        MachineConfig {
+            model: None,
+            rom: None,
+            char_rom: None,
+            ram: None,
+            ram_init: None,
+            slots: [None, None, None, None, None, None, None, None],
+        }
+    }
+
+    /* The layout used when nothing else is configured. */
+    pub fn default_layout(model: Model) -> MachineConfig {
+        let mut config = MachineConfig::new();
+        config.model = Some(model);
+        if !model.has_builtin_language_card() {
+            config.slots[0] = Some(CardConfig::parse("language", ' ').unwrap());
+        }
+        config.slots[6] = Some(CardConfig::parse("disk2 drive1=diskii.img", ' ').unwrap());
+        config
+    }
+
+    pub fn from_file(path: &str) -> Result<MachineConfig, ConfigError> {
+        let file = try!(fs::File::open(path).map_err(|err| ConfigError::Io(path.to_string(), err)));
+        let mut config = MachineConfig::new();
+
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = try!(line.map_err(|err| ConfigError::Io(path.to_string(), err)));
+            try!(config.parse_line(&line)
+                .map_err(|err| ConfigError::Line(path.to_string(), idx + 1, Box::new(err))));
+        }
+
+        Ok(config)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), ConfigError> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let mut kv = line.splitn(2, '=');
+        let key = kv.next().unwrap().trim();
+        let val = match kv.next() {
+            Some(val) => val.trim(),
+            None => return Err(ConfigError::Syntax(format!("expected key = value, found \"{}\"", line))),
+        };
+
+        if key == "model" {
+            if self.model.is_some() {
+                return Err(ConfigError::Syntax("model given twice".to_string()));
+            }
+            self.model = Some(try!(parse_model(val)));
+            Ok(())
+        } else if key == "ram" {
+            if self.ram.is_some() {
+                return Err(ConfigError::Syntax("ram given twice".to_string()));
+            }
+            self.ram = Some(try!(parse_ram(val)));
+            Ok(())
+        } else if key == "ram_init" {
+            if self.ram_init.is_some() {
+                return Err(ConfigError::Syntax("ram_init given twice".to_string()));
+            }
+            self.ram_init = Some(try!(parse_ram_init(val)));
+            Ok(())
+        } else if key == "rom" {
+            if self.rom.is_some() {
+                return Err(ConfigError::Syntax("rom given twice".to_string()));
+            }
+            self.rom = Some(val.to_string());
+            Ok(())
+        } else if key == "charrom" {
+            if self.char_rom.is_some() {
+                return Err(ConfigError::Syntax("charrom given twice".to_string()));
+            }
+            self.char_rom = Some(val.to_string());
+            Ok(())
+        } else if key.starts_with("slot") {
+            let slot = try!(parse_slot(&key[4..]));
+            let card = try!(CardConfig::parse(val, ' '));
+            self.set_slot(slot, card)
+        } else {
+            Err(ConfigError::Syntax(format!("unknown setting \"{}\"", key)))
+        }
+    }
+
+    /* Parses a command line card spec of the form "6=disk2,drive1=foo.dsk". */
+    pub fn parse_slot_arg(arg: &str) -> Result<(usize, CardConfig), ConfigError> {
+        let mut kv = arg.splitn(2, '=');
+        let slot = try!(parse_slot(kv.next().unwrap()));
+        let card = match kv.next() {
+            Some(spec) => try!(CardConfig::parse(spec, ',')),
+            None => return Err(ConfigError::Syntax(format!("expected SLOT=CARD, found \"{}\"", arg))),
+        };
+        Ok((slot, card))
+    }
+
+    pub fn set_slot(&mut self, slot: usize, card: CardConfig) -> Result<(), ConfigError> {
+        if let Some(ref existing) = self.slots[slot] {
+            return Err(ConfigError::SlotConflict(slot, existing.kind, card.kind));
+        }
+        self.slots[slot] = Some(card);
+        Ok(())
+    }
+
+    /* Machines default to the II+ when no model is given. */
+    pub fn model(&self) -> Model {
+        self.model.unwrap_or(Model::AppleIIPlus)
+    }
+
+    /* Everything comes with a full 48K unless told otherwise. */
+    pub fn ram(&self) -> usize {
+        self.ram.unwrap_or(48)
+    }
+
+    /* Zeroed RAM unless asked for something closer to real hardware. */
+    pub fn ram_init(&self) -> RamInit {
+        self.ram_init.unwrap_or(RamInit::Zero)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let model = self.model();
+        if let Some(ram) = self.ram {
+            if !RAM_SIZES.contains(&ram) {
+                return Err(ConfigError::RamSize(ram));
+            }
+            if ram != 48 && !model.has_configurable_ram() {
+                return Err(ConfigError::FixedRam(model));
+            }
+        }
+
+        for (slot, card) in self.slots.iter().enumerate() {
+            let card = match *card {
+                Some(ref card) => card,
+                None => continue,
+            };
+
+            /* the //c's only configurable port is its built-in disk controller */
+            let allowed = if model.has_slots() {
+                !(slot == 0 && model.has_builtin_language_card())
+            } else {
+                slot == 6 && card.kind == CardKind::DiskII
+            };
+            if !allowed {
+                return Err(ConfigError::BuiltinSlot(slot, model, card.kind));
+            }
+
+            /* slot 0 on the II+ only decodes $C080-$C08F and the language rom space */
+            if card.kind.is_language_card() != (slot == 0) {
+                return Err(ConfigError::WrongSlot(slot, card.kind));
+            }
+
+            for key in card.params.keys() {
+                if !card.kind.required_params().contains(&key.as_str()) &&
+                   !card.kind.optional_params().contains(&key.as_str()) {
+                    return Err(ConfigError::UnknownParam(slot, card.kind, key.clone()));
+                }
+            }
+
+            for key in card.kind.required_params() {
+                if !card.params.contains_key(*key) {
+                    return Err(ConfigError::MissingParam(slot, card.kind, *key));
+                }
+            }
+
+            for key in card.kind.file_params() {
+                if let Some(path) = card.param(key) {
+                    if let Err(err) = fs::metadata(path) {
+                        return Err(ConfigError::Io(path.to_string(), err));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_slot(slot: &str) -> Result<usize, ConfigError> {
+    match slot.trim().parse::<usize>() {
+        Ok(slot) if slot < NUM_SLOTS => Ok(slot),
+        _ => Err(ConfigError::BadSlot(slot.trim().to_string())),
+    }
+}
+
+/* RAM sizes are in K, with or without the K. */
+pub fn parse_ram(size: &str) -> Result<usize, ConfigError> {
+    let size = size.trim();
+    let digits = size.trim_right_matches(|ch| ch == 'K' || ch == 'k');
+    digits.parse::<usize>()
+        .map_err(|_| ConfigError::Syntax(format!("invalid RAM size \"{}\"", size)))
+}
+
+pub fn parse_ram_init(spec: &str) -> Result<RamInit, ConfigError> {
+    RamInit::from_spec(spec).ok_or_else(|| {
+        ConfigError::Syntax(format!("invalid ram_init \"{}\", use zero, pattern, random or random:SEED",
+                                    spec.trim()))
+    })
+}
+
+pub fn parse_model(name: &str) -> Result<Model, ConfigError> {
+    Model::from_name(name.trim()).ok_or_else(|| ConfigError::UnknownModel(name.trim().to_string()))
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, io::Error),
+    Line(String, usize, Box<ConfigError>),
+    Syntax(String),
+    BadSlot(String),
+    UnknownModel(String),
+    RamSize(usize),
+    FixedRam(Model),
+    BuiltinSlot(usize, Model, CardKind),
+    UnknownCard(String),
+    SlotConflict(usize, CardKind, CardKind),
+    WrongSlot(usize, CardKind),
+    UnknownParam(usize, CardKind, String),
+    MissingParam(usize, CardKind, &'static str),
+    FileSize(String, usize, usize),
+    CharRom(String, &'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref path, ref err) => write!(f, "{}: {}", path, err),
+            ConfigError::Line(ref path, line, ref err) => write!(f, "{}:{}: {}", path, line, err),
+            ConfigError::Syntax(ref msg) => write!(f, "{}", msg),
+            ConfigError::BadSlot(ref slot) => {
+                write!(f, "invalid slot \"{}\", slots are 0 to {}", slot, NUM_SLOTS - 1)
+            }
+            ConfigError::UnknownModel(ref name) => {
+                write!(f, "unknown model \"{}\", models are ii, iiplus, iie, iieenh and iic", name)
+            }
+            ConfigError::RamSize(ram) => {
+                write!(f,
+                       "cannot fit {}K of RAM, sizes are {}",
+                       ram,
+                       RAM_SIZES.iter().map(|size| size.to_string()).collect::<Vec<_>>().join(", "))
+            }
+            ConfigError::FixedRam(model) => {
+                write!(f, "the {} always has 48K of main RAM", model.name())
+            }
+            ConfigError::BuiltinSlot(slot, model, card) => {
+                write!(f,
+                       "{} card cannot go in slot {} on the {}",
+                       card.name(),
+                       slot,
+                       model.name())
+            }
+            ConfigError::UnknownCard(ref name) => write!(f, "unknown card \"{}\"", name),
+            ConfigError::SlotConflict(slot, existing, card) => {
+                write!(f,
+                       "slot {} already holds a {} card, cannot also add a {} card",
+                       slot,
+                       existing.name(),
+                       card.name())
+            }
+            ConfigError::WrongSlot(slot, card) => {
+                if card.is_language_card() {
+                    write!(f, "{} card must go in slot 0, not slot {}", card.name(), slot)
+                } else {
+                    write!(f, "{} card cannot go in slot 0", card.name())
+                }
+            }
+            ConfigError::UnknownParam(slot, card, ref key) => {
+                write!(f,
+                       "{} card in slot {} has no parameter \"{}\"",
+                       card.name(),
+                       slot,
+                       key)
+            }
+            ConfigError::MissingParam(slot, card, key) => {
+                write!(f,
+                       "{} card in slot {} needs parameter \"{}\"",
+                       card.name(),
+                       slot,
+                       key)
+            }
+            ConfigError::FileSize(ref path, expected, found) => {
+                write!(f,
+                       "{}: expected {} bytes, found {} bytes",
+                       path,
+                       expected,
+                       found)
+            }
+            ConfigError::CharRom(ref path, msg) => {
+                write!(f, "{}: not a character generator rom, {}", path, msg)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn description(&self) -> &str {
+        "invalid machine configuration"
+    }
+}
@@ -0,0 +1,96 @@
+use r6502::cpu6502::Cpu6502;
+use r6502::memory::Memory;
+
+use cpu65c02::Cpu65C02;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuKind {
+    Nmos6502,
+    Cmos65C02,
+}
+
+impl CpuKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CpuKind::Nmos6502 => "6502",
+            CpuKind::Cmos65C02 => "65C02",
+        }
+    }
+}
+
+/* The processor a machine runs, picked by its model. */
+pub enum Cpu<M: Memory<u8>> {
+    Nmos(Cpu6502<M>),
+    Cmos(Cpu65C02<M>),
+}
+
+impl<M: Memory<u8>> Cpu<M> {
+    pub fn new(kind: CpuKind, memory: M) -> Cpu<M> {
+        match kind {
+            CpuKind::Nmos6502 => Cpu::Nmos(Cpu6502::new(memory)),
+            CpuKind::Cmos65C02 => Cpu::Cmos(Cpu65C02::new(memory)),
+        }
+    }
+
+    pub fn memory(&self) -> &M {
+        match *self {
+            Cpu::Nmos(ref cpu) => &cpu.memory,
+            Cpu::Cmos(ref cpu) => &cpu.memory,
+        }
+    }
+
+    pub fn memory_mut(&mut self) -> &mut M {
+        match *self {
+            Cpu::Nmos(ref mut cpu) => &mut cpu.memory,
+            Cpu::Cmos(ref mut cpu) => &mut cpu.memory,
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        match *self {
+            Cpu::Nmos(ref cpu) => cpu.cycles,
+            Cpu::Cmos(ref cpu) => cpu.cycles,
+        }
+    }
+
+    /* For time the cpu spends halted, like while a card masters the bus. */
+    pub fn add_cycles(&mut self, cycles: u64) {
+        match *self {
+            Cpu::Nmos(ref mut cpu) => cpu.cycles += cycles,
+            Cpu::Cmos(ref mut cpu) => cpu.cycles += cycles,
+        }
+    }
+
+    /* Runs a single instruction. */
+    pub fn step(&mut self) {
+        match *self {
+            Cpu::Nmos(ref mut cpu) => {
+                cpu.run(1).expect("AAAAA CPU DIED");
+            }
+            Cpu::Cmos(ref mut cpu) => {
+                cpu.step();
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        match *self {
+            Cpu::Nmos(ref mut cpu) => cpu.reset(),
+            Cpu::Cmos(ref mut cpu) => cpu.reset(),
+        }
+    }
+
+    pub fn irq(&mut self) {
+        match *self {
+            Cpu::Nmos(ref mut cpu) => cpu.irq(),
+            Cpu::Cmos(ref mut cpu) => cpu.irq(),
+        }
+    }
+
+    pub fn nmi(&mut self) {
+        match *self {
+            Cpu::Nmos(ref mut cpu) => cpu.nmi(),
+            Cpu::Cmos(ref mut cpu) => cpu.nmi(),
+        }
+    }
+}
@@ -0,0 +1,923 @@
+use r6502::memory::Memory;
+
+/* A 65C02 core, for the enhanced //e and the //c.
+ *
+ * On top of the NMOS instruction set it has the CMOS additions (BRA,
+ * STZ, PHX/PLX/PHY/PLY, TSB/TRB, INC/DEC A, BIT #, (zp) addressing and
+ * JMP (abs,x)) and the WDC WAI/STP. The Rockwell RMB/SMB/BBR/BBS bit
+ * instructions are not there on the parts Apple used, so $x7 and $xF
+ * are one byte, one cycle NOPs like the other unused opcodes, which
+ * take their documented lengths and timings.
+ *
+ * Differences from the NMOS part that software can see:
+ *   - JMP ($xxFF) fetches the high byte from the next page
+ *   - decimal ADC/SBC set N and Z from the result and take a cycle more
+ *   - BRK and interrupts clear D
+ */
+
+const FLAG_C: u8 = 0x01;
+const FLAG_Z: u8 = 0x02;
+const FLAG_I: u8 = 0x04;
+const FLAG_D: u8 = 0x08;
+const FLAG_B: u8 = 0x10;
+const FLAG_U: u8 = 0x20;
+const FLAG_V: u8 = 0x40;
+const FLAG_N: u8 = 0x80;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/* Base cycle counts. Page crossings, taken branches and decimal mode
+ * add to these as the instruction runs.
+ */
+static CYCLES: [u8; 0x100] = [
+    7, 6, 2, 1, 5, 3, 5, 1, 3, 2, 2, 1, 6, 4, 6, 1,
+    2, 5, 5, 1, 5, 4, 6, 1, 2, 4, 2, 1, 6, 4, 6, 1,
+    6, 6, 2, 1, 3, 3, 5, 1, 4, 2, 2, 1, 4, 4, 6, 1,
+    2, 5, 5, 1, 4, 4, 6, 1, 2, 4, 2, 1, 4, 4, 6, 1,
+    6, 6, 2, 1, 3, 3, 5, 1, 3, 2, 2, 1, 3, 4, 6, 1,
+    2, 5, 5, 1, 4, 4, 6, 1, 2, 4, 3, 1, 8, 4, 6, 1,
+    6, 6, 2, 1, 3, 3, 5, 1, 4, 2, 2, 1, 6, 4, 6, 1,
+    2, 5, 5, 1, 4, 4, 6, 1, 2, 4, 4, 1, 6, 4, 6, 1,
+    2, 6, 2, 1, 3, 3, 3, 1, 2, 2, 2, 1, 4, 4, 4, 1,
+    2, 6, 5, 1, 4, 4, 4, 1, 2, 5, 2, 1, 4, 5, 5, 1,
+    2, 6, 2, 1, 3, 3, 3, 1, 2, 2, 2, 1, 4, 4, 4, 1,
+    2, 5, 5, 1, 4, 4, 4, 1, 2, 4, 2, 1, 4, 4, 4, 1,
+    2, 6, 2, 1, 3, 3, 5, 1, 2, 2, 2, 3, 4, 4, 6, 1,
+    2, 5, 5, 1, 4, 4, 6, 1, 2, 4, 3, 3, 4, 4, 7, 1,
+    2, 6, 2, 1, 3, 3, 5, 1, 2, 2, 2, 1, 4, 4, 6, 1,
+    2, 5, 5, 1, 4, 4, 6, 1, 2, 4, 4, 1, 4, 4, 7, 1,
+];
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Imm,
+    Zp,
+    ZpX,
+    ZpY,
+    Abs,
+    AbsX,
+    AbsY,
+    IndX,
+    IndY,
+    ZpInd,
+}
+
+/* Addressing for the ORA/AND/EOR/ADC/STA/LDA/CMP/SBC group,
+ * picked by the low five bits of the opcode.
+ */
+fn alu_mode(op: u8) -> Mode {
+    match op & 0x1F {
+        0x01 => Mode::IndX,
+        0x05 => Mode::Zp,
+        0x09 => Mode::Imm,
+        0x0D => Mode::Abs,
+        0x11 => Mode::IndY,
+        0x12 => Mode::ZpInd,
+        0x15 => Mode::ZpX,
+        0x19 => Mode::AbsY,
+        _ => Mode::AbsX,
+    }
+}
+
+pub struct Cpu65C02<M: Memory<u8>> {
+    pub memory: M,
+    pub cycles: u64,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub p: u8,
+    pub pc: u16,
+    /* WAI, until the next interrupt */
+    waiting: bool,
+    /* STP, until the next reset */
+    stopped: bool,
+    /* set when indexing crossed a page */
+    crossed: bool,
+    /* cycles over the base count for the current instruction */
+    extra: u32,
+}
+
+impl<M: Memory<u8>> Cpu65C02<M> {
+    pub fn new(memory: M) -> Cpu65C02<M> {
+        Cpu65C02 {
+            memory: memory,
+            cycles: 0,
+            a: 0,
+            x: 0,
+            y: 0,
+            sp: 0xFD,
+            p: FLAG_U | FLAG_I,
+            pc: 0,
+            waiting: false,
+            stopped: false,
+            crossed: false,
+            extra: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.sp = self.sp.wrapping_sub(3);
+        self.p = (self.p | FLAG_I | FLAG_U) & !FLAG_D;
+        self.pc = self.read16(RESET_VECTOR);
+        self.waiting = false;
+        self.stopped = false;
+        self.cycles += 7;
+    }
+
+    pub fn irq(&mut self) {
+        /* WAI wakes up on IRQ even when it is masked */
+        self.waiting = false;
+        if self.p & FLAG_I == 0 {
+            self.interrupt(IRQ_VECTOR, false);
+            self.cycles += 7;
+        }
+    }
+
+    pub fn nmi(&mut self) {
+        self.waiting = false;
+        self.interrupt(NMI_VECTOR, false);
+        self.cycles += 7;
+    }
+
+    /* Runs one instruction and returns the cycles it took. */
+    pub fn step(&mut self) -> u32 {
+        if self.waiting || self.stopped {
+            self.cycles += 1;
+            return 1;
+        }
+
+        let op = self.fetch();
+        self.crossed = false;
+        self.extra = 0;
+        self.exec(op);
+
+        let taken = CYCLES[op as usize] as u32 + self.extra;
+        self.cycles += taken as u64;
+        taken
+    }
+
+    fn interrupt(&mut self, vector: u16, brk: bool) {
+        let pc = self.pc;
+        self.push16(pc);
+        let p = if brk {
+            self.p | FLAG_B | FLAG_U
+        } else {
+            (self.p & !FLAG_B) | FLAG_U
+        };
+        self.push(p);
+        self.p = (self.p | FLAG_I) & !FLAG_D;
+        self.pc = self.read16(vector);
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let val = self.memory.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        val
+    }
+
+    fn fetch16(&mut self) -> u16 {
+        let lo = self.fetch() as u16;
+        let hi = self.fetch() as u16;
+        (hi << 8) | lo
+    }
+
+    fn read16(&mut self, addr: u16) -> u16 {
+        let lo = self.memory.read(addr) as u16;
+        let hi = self.memory.read(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    /* Pointers in zero page wrap around within it. */
+    fn read_zp16(&mut self, zp: u8) -> u16 {
+        let lo = self.memory.read(zp as u16) as u16;
+        let hi = self.memory.read(zp.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
+    fn push(&mut self, val: u8) {
+        let addr = 0x100 | self.sp as u16;
+        self.memory.write(addr, val);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn push16(&mut self, val: u16) {
+        self.push((val >> 8) as u8);
+        self.push(val as u8);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        let addr = 0x100 | self.sp as u16;
+        self.memory.read(addr)
+    }
+
+    fn pull16(&mut self) -> u16 {
+        let lo = self.pull() as u16;
+        let hi = self.pull() as u16;
+        (hi << 8) | lo
+    }
+
+    fn indexed(&mut self, base: u16, index: u8) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        if addr & 0xFF00 != base & 0xFF00 {
+            self.crossed = true;
+        }
+        addr
+    }
+
+    fn operand_addr(&mut self, mode: Mode) -> u16 {
+        match mode {
+            Mode::Imm => {
+                let addr = self.pc;
+                self.pc = self.pc.wrapping_add(1);
+                addr
+            }
+            Mode::Zp => self.fetch() as u16,
+            Mode::ZpX => {
+                let x = self.x;
+                self.fetch().wrapping_add(x) as u16
+            }
+            Mode::ZpY => {
+                let y = self.y;
+                self.fetch().wrapping_add(y) as u16
+            }
+            Mode::Abs => self.fetch16(),
+            Mode::AbsX => {
+                let base = self.fetch16();
+                let x = self.x;
+                self.indexed(base, x)
+            }
+            Mode::AbsY => {
+                let base = self.fetch16();
+                let y = self.y;
+                self.indexed(base, y)
+            }
+            Mode::IndX => {
+                let x = self.x;
+                let zp = self.fetch().wrapping_add(x);
+                self.read_zp16(zp)
+            }
+            Mode::IndY => {
+                let zp = self.fetch();
+                let base = self.read_zp16(zp);
+                let y = self.y;
+                self.indexed(base, y)
+            }
+            Mode::ZpInd => {
+                let zp = self.fetch();
+                self.read_zp16(zp)
+            }
+        }
+    }
+
+    /* Loads take a cycle more when indexing crosses a page. */
+    fn read_operand(&mut self, mode: Mode) -> u8 {
+        let addr = self.operand_addr(mode);
+        if self.crossed {
+            self.extra += 1;
+        }
+        self.memory.read(addr)
+    }
+
+    fn write_operand(&mut self, mode: Mode, val: u8) {
+        let addr = self.operand_addr(mode);
+        self.memory.write(addr, val);
+    }
+
+    fn modify(&mut self, mode: Mode, op: fn(&mut Cpu65C02<M>, u8) -> u8) {
+        let addr = self.operand_addr(mode);
+        let val = self.memory.read(addr);
+        let val = op(self, val);
+        self.memory.write(addr, val);
+    }
+
+    /* Shifts and rotates on abs,x only take the extra cycle on a page crossing. */
+    fn modify_shift(&mut self, mode: Mode, op: fn(&mut Cpu65C02<M>, u8) -> u8) {
+        self.modify(mode, op);
+        if self.crossed {
+            self.extra += 1;
+        }
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+
+    fn set_nz(&mut self, val: u8) {
+        self.set_flag(FLAG_Z, val == 0);
+        self.set_flag(FLAG_N, val & 0x80 != 0);
+    }
+
+    fn branch(&mut self, cond: bool) {
+        let offset = self.fetch() as i8;
+        self.branch_by(offset, cond);
+    }
+
+    fn branch_by(&mut self, offset: i8, cond: bool) {
+        if cond {
+            let target = self.pc.wrapping_add(offset as u16);
+            self.extra += 1;
+            if target & 0xFF00 != self.pc & 0xFF00 {
+                self.extra += 1;
+            }
+            self.pc = target;
+        }
+    }
+
+    fn adc(&mut self, val: u8) {
+        let carry = (self.p & FLAG_C) as u16;
+        let a = self.a as u16;
+        let val16 = val as u16;
+        if self.p & FLAG_D != 0 {
+            let mut lo = (a & 0x0F) + (val16 & 0x0F) + carry;
+            if lo >= 0x0A {
+                lo = ((lo + 0x06) & 0x0F) + 0x10;
+            }
+            let mut sum = (a & 0xF0) + (val16 & 0xF0) + lo;
+            let overflow = !(self.a ^ val) & (self.a ^ sum as u8) & 0x80 != 0;
+            if sum >= 0xA0 {
+                sum += 0x60;
+            }
+            self.set_flag(FLAG_V, overflow);
+            self.set_flag(FLAG_C, sum > 0xFF);
+            self.a = sum as u8;
+            self.extra += 1;
+        } else {
+            let sum = a + val16 + carry;
+            self.set_flag(FLAG_V, !(self.a ^ val) & (self.a ^ sum as u8) & 0x80 != 0);
+            self.set_flag(FLAG_C, sum > 0xFF);
+            self.a = sum as u8;
+        }
+        let a = self.a;
+        self.set_nz(a);
+    }
+
+    fn sbc(&mut self, val: u8) {
+        let borrow = 1 - (self.p & FLAG_C) as i16;
+        let a = self.a as i16;
+        let val16 = val as i16;
+        let diff = a - val16 - borrow;
+        self.set_flag(FLAG_V, (self.a ^ val) & (self.a ^ diff as u8) & 0x80 != 0);
+        self.set_flag(FLAG_C, diff >= 0);
+        if self.p & FLAG_D != 0 {
+            let lo = (a & 0x0F) - (val16 & 0x0F) - borrow;
+            let mut res = diff;
+            if res < 0 {
+                res -= 0x60;
+            }
+            if lo < 0 {
+                res -= 0x06;
+            }
+            self.a = res as u8;
+            self.extra += 1;
+        } else {
+            self.a = diff as u8;
+        }
+        let a = self.a;
+        self.set_nz(a);
+    }
+
+    fn compare(&mut self, reg: u8, val: u8) {
+        self.set_flag(FLAG_C, reg >= val);
+        self.set_nz(reg.wrapping_sub(val));
+    }
+
+    fn bit(&mut self, val: u8) {
+        let a = self.a;
+        self.set_flag(FLAG_Z, a & val == 0);
+        self.set_flag(FLAG_N, val & 0x80 != 0);
+        self.set_flag(FLAG_V, val & 0x40 != 0);
+    }
+
+    fn asl(&mut self, val: u8) -> u8 {
+        self.set_flag(FLAG_C, val & 0x80 != 0);
+        let res = val << 1;
+        self.set_nz(res);
+        res
+    }
+
+    fn lsr(&mut self, val: u8) -> u8 {
+        self.set_flag(FLAG_C, val & 0x01 != 0);
+        let res = val >> 1;
+        self.set_nz(res);
+        res
+    }
+
+    fn rol(&mut self, val: u8) -> u8 {
+        let carry = self.p & FLAG_C;
+        self.set_flag(FLAG_C, val & 0x80 != 0);
+        let res = (val << 1) | carry;
+        self.set_nz(res);
+        res
+    }
+
+    fn ror(&mut self, val: u8) -> u8 {
+        let carry = (self.p & FLAG_C) << 7;
+        self.set_flag(FLAG_C, val & 0x01 != 0);
+        let res = (val >> 1) | carry;
+        self.set_nz(res);
+        res
+    }
+
+    fn inc(&mut self, val: u8) -> u8 {
+        let res = val.wrapping_add(1);
+        self.set_nz(res);
+        res
+    }
+
+    fn dec(&mut self, val: u8) -> u8 {
+        let res = val.wrapping_sub(1);
+        self.set_nz(res);
+        res
+    }
+
+    fn tsb(&mut self, val: u8) -> u8 {
+        let a = self.a;
+        self.set_flag(FLAG_Z, a & val == 0);
+        val | a
+    }
+
+    fn trb(&mut self, val: u8) -> u8 {
+        let a = self.a;
+        self.set_flag(FLAG_Z, a & val == 0);
+        val & !a
+    }
+
+    /* ORA, AND, EOR, ADC, STA, LDA, CMP and SBC by bits 7-5. */
+    fn alu(&mut self, op: u8) {
+        let mode = alu_mode(op);
+        if op >> 5 == 4 {
+            let a = self.a;
+            self.write_operand(mode, a);
+            return;
+        }
+
+        let val = self.read_operand(mode);
+        match op >> 5 {
+            0 => self.a |= val,
+            1 => self.a &= val,
+            2 => self.a ^= val,
+            3 => return self.adc(val),
+            5 => self.a = val,
+            6 => {
+                let a = self.a;
+                return self.compare(a, val);
+            }
+            _ => return self.sbc(val),
+        }
+        let a = self.a;
+        self.set_nz(a);
+    }
+
+    fn exec(&mut self, op: u8) {
+        match op {
+            /* BIT # only touches Z */
+            0x89 => {
+                let val = self.read_operand(Mode::Imm);
+                let a = self.a;
+                self.set_flag(FLAG_Z, a & val == 0);
+            }
+            _ if op & 0x03 == 0x01 || op & 0x1F == 0x12 => self.alu(op),
+
+            0x00 => {
+                /* skip the signature byte */
+                self.pc = self.pc.wrapping_add(1);
+                self.interrupt(IRQ_VECTOR, true);
+            }
+            0x20 => {
+                let addr = self.fetch16();
+                let ret = self.pc.wrapping_sub(1);
+                self.push16(ret);
+                self.pc = addr;
+            }
+            0x40 => {
+                let p = self.pull();
+                self.p = (p & !FLAG_B) | FLAG_U;
+                self.pc = self.pull16();
+            }
+            0x60 => self.pc = self.pull16().wrapping_add(1),
+            0x4C => self.pc = self.fetch16(),
+            0x6C => {
+                let ptr = self.fetch16();
+                self.pc = self.read16(ptr);
+            }
+            0x7C => {
+                let ptr = self.fetch16().wrapping_add(self.x as u16);
+                self.pc = self.read16(ptr);
+            }
+
+            0x10 => {
+                let cond = self.p & FLAG_N == 0;
+                self.branch(cond)
+            }
+            0x30 => {
+                let cond = self.p & FLAG_N != 0;
+                self.branch(cond)
+            }
+            0x50 => {
+                let cond = self.p & FLAG_V == 0;
+                self.branch(cond)
+            }
+            0x70 => {
+                let cond = self.p & FLAG_V != 0;
+                self.branch(cond)
+            }
+            0x80 => self.branch(true),
+            0x90 => {
+                let cond = self.p & FLAG_C == 0;
+                self.branch(cond)
+            }
+            0xB0 => {
+                let cond = self.p & FLAG_C != 0;
+                self.branch(cond)
+            }
+            0xD0 => {
+                let cond = self.p & FLAG_Z == 0;
+                self.branch(cond)
+            }
+            0xF0 => {
+                let cond = self.p & FLAG_Z != 0;
+                self.branch(cond)
+            }
+
+            0x08 => {
+                let p = self.p | FLAG_B | FLAG_U;
+                self.push(p);
+            }
+            0x28 => {
+                let p = self.pull();
+                self.p = (p & !FLAG_B) | FLAG_U;
+            }
+            0x48 => {
+                let a = self.a;
+                self.push(a);
+            }
+            0x68 => {
+                self.a = self.pull();
+                let a = self.a;
+                self.set_nz(a);
+            }
+            0x5A => {
+                let y = self.y;
+                self.push(y);
+            }
+            0x7A => {
+                self.y = self.pull();
+                let y = self.y;
+                self.set_nz(y);
+            }
+            0xDA => {
+                let x = self.x;
+                self.push(x);
+            }
+            0xFA => {
+                self.x = self.pull();
+                let x = self.x;
+                self.set_nz(x);
+            }
+
+            0x18 => self.p &= !FLAG_C,
+            0x38 => self.p |= FLAG_C,
+            0x58 => self.p &= !FLAG_I,
+            0x78 => self.p |= FLAG_I,
+            0xB8 => self.p &= !FLAG_V,
+            0xD8 => self.p &= !FLAG_D,
+            0xF8 => self.p |= FLAG_D,
+
+            0x0A => {
+                let a = self.a;
+                self.a = self.asl(a);
+            }
+            0x06 => self.modify(Mode::Zp, Cpu65C02::asl),
+            0x0E => self.modify(Mode::Abs, Cpu65C02::asl),
+            0x16 => self.modify(Mode::ZpX, Cpu65C02::asl),
+            0x1E => self.modify_shift(Mode::AbsX, Cpu65C02::asl),
+            0x2A => {
+                let a = self.a;
+                self.a = self.rol(a);
+            }
+            0x26 => self.modify(Mode::Zp, Cpu65C02::rol),
+            0x2E => self.modify(Mode::Abs, Cpu65C02::rol),
+            0x36 => self.modify(Mode::ZpX, Cpu65C02::rol),
+            0x3E => self.modify_shift(Mode::AbsX, Cpu65C02::rol),
+            0x4A => {
+                let a = self.a;
+                self.a = self.lsr(a);
+            }
+            0x46 => self.modify(Mode::Zp, Cpu65C02::lsr),
+            0x4E => self.modify(Mode::Abs, Cpu65C02::lsr),
+            0x56 => self.modify(Mode::ZpX, Cpu65C02::lsr),
+            0x5E => self.modify_shift(Mode::AbsX, Cpu65C02::lsr),
+            0x6A => {
+                let a = self.a;
+                self.a = self.ror(a);
+            }
+            0x66 => self.modify(Mode::Zp, Cpu65C02::ror),
+            0x6E => self.modify(Mode::Abs, Cpu65C02::ror),
+            0x76 => self.modify(Mode::ZpX, Cpu65C02::ror),
+            0x7E => self.modify_shift(Mode::AbsX, Cpu65C02::ror),
+            0x1A => {
+                let a = self.a;
+                self.a = self.inc(a);
+            }
+            0xE6 => self.modify(Mode::Zp, Cpu65C02::inc),
+            0xEE => self.modify(Mode::Abs, Cpu65C02::inc),
+            0xF6 => self.modify(Mode::ZpX, Cpu65C02::inc),
+            0xFE => self.modify(Mode::AbsX, Cpu65C02::inc),
+            0x3A => {
+                let a = self.a;
+                self.a = self.dec(a);
+            }
+            0xC6 => self.modify(Mode::Zp, Cpu65C02::dec),
+            0xCE => self.modify(Mode::Abs, Cpu65C02::dec),
+            0xD6 => self.modify(Mode::ZpX, Cpu65C02::dec),
+            0xDE => self.modify(Mode::AbsX, Cpu65C02::dec),
+            0x04 => self.modify(Mode::Zp, Cpu65C02::tsb),
+            0x0C => self.modify(Mode::Abs, Cpu65C02::tsb),
+            0x14 => self.modify(Mode::Zp, Cpu65C02::trb),
+            0x1C => self.modify(Mode::Abs, Cpu65C02::trb),
+
+            0x24 | 0x2C | 0x34 | 0x3C => {
+                let mode = match op {
+                    0x24 => Mode::Zp,
+                    0x2C => Mode::Abs,
+                    0x34 => Mode::ZpX,
+                    _ => Mode::AbsX,
+                };
+                let val = self.read_operand(mode);
+                self.bit(val);
+            }
+
+            0x64 => self.write_operand(Mode::Zp, 0),
+            0x74 => self.write_operand(Mode::ZpX, 0),
+            0x9C => self.write_operand(Mode::Abs, 0),
+            0x9E => self.write_operand(Mode::AbsX, 0),
+            0x84 | 0x8C | 0x94 => {
+                let mode = match op {
+                    0x84 => Mode::Zp,
+                    0x8C => Mode::Abs,
+                    _ => Mode::ZpX,
+                };
+                let y = self.y;
+                self.write_operand(mode, y);
+            }
+            0x86 | 0x8E | 0x96 => {
+                let mode = match op {
+                    0x86 => Mode::Zp,
+                    0x8E => Mode::Abs,
+                    _ => Mode::ZpY,
+                };
+                let x = self.x;
+                self.write_operand(mode, x);
+            }
+            0xA0 | 0xA4 | 0xAC | 0xB4 | 0xBC => {
+                let mode = match op {
+                    0xA0 => Mode::Imm,
+                    0xA4 => Mode::Zp,
+                    0xAC => Mode::Abs,
+                    0xB4 => Mode::ZpX,
+                    _ => Mode::AbsX,
+                };
+                self.y = self.read_operand(mode);
+                let y = self.y;
+                self.set_nz(y);
+            }
+            0xA2 | 0xA6 | 0xAE | 0xB6 | 0xBE => {
+                let mode = match op {
+                    0xA2 => Mode::Imm,
+                    0xA6 => Mode::Zp,
+                    0xAE => Mode::Abs,
+                    0xB6 => Mode::ZpY,
+                    _ => Mode::AbsY,
+                };
+                self.x = self.read_operand(mode);
+                let x = self.x;
+                self.set_nz(x);
+            }
+            0xC0 | 0xC4 | 0xCC => {
+                let mode = match op {
+                    0xC0 => Mode::Imm,
+                    0xC4 => Mode::Zp,
+                    _ => Mode::Abs,
+                };
+                let val = self.read_operand(mode);
+                let y = self.y;
+                self.compare(y, val);
+            }
+            0xE0 | 0xE4 | 0xEC => {
+                let mode = match op {
+                    0xE0 => Mode::Imm,
+                    0xE4 => Mode::Zp,
+                    _ => Mode::Abs,
+                };
+                let val = self.read_operand(mode);
+                let x = self.x;
+                self.compare(x, val);
+            }
+
+            0x88 => {
+                self.y = self.y.wrapping_sub(1);
+                let y = self.y;
+                self.set_nz(y);
+            }
+            0xC8 => {
+                self.y = self.y.wrapping_add(1);
+                let y = self.y;
+                self.set_nz(y);
+            }
+            0xCA => {
+                self.x = self.x.wrapping_sub(1);
+                let x = self.x;
+                self.set_nz(x);
+            }
+            0xE8 => {
+                self.x = self.x.wrapping_add(1);
+                let x = self.x;
+                self.set_nz(x);
+            }
+            0x8A => {
+                self.a = self.x;
+                let a = self.a;
+                self.set_nz(a);
+            }
+            0x98 => {
+                self.a = self.y;
+                let a = self.a;
+                self.set_nz(a);
+            }
+            0xAA => {
+                self.x = self.a;
+                let x = self.x;
+                self.set_nz(x);
+            }
+            0xA8 => {
+                self.y = self.a;
+                let y = self.y;
+                self.set_nz(y);
+            }
+            0xBA => {
+                self.x = self.sp;
+                let x = self.x;
+                self.set_nz(x);
+            }
+            0x9A => self.sp = self.x,
+
+            0xCB => self.waiting = true,
+            0xDB => self.stopped = true,
+
+            /* unused opcodes, as NOPs that still fetch their operands */
+            0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xC2 | 0xE2 | 0x44 | 0x54 | 0xD4 | 0xF4 => {
+                self.fetch();
+            }
+            0x5C | 0xDC | 0xFC => {
+                self.fetch16();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r6502::memory::Memory;
+
+    struct Ram(Vec<u8>);
+
+    impl Memory<u8> for Ram {
+        fn read_without_mm(&mut self, addr: u16) -> u8 {
+            self.0[addr as usize]
+        }
+
+        fn write_without_mm(&mut self, addr: u16, val: u8) {
+            self.0[addr as usize] = val;
+        }
+    }
+
+    /* A CPU reset into a program at $0200. */
+    fn cpu(program: &[u8]) -> Cpu65C02<Ram> {
+        let mut ram = Ram(vec![0; 0x10000]);
+        ram.0[0x200..0x200 + program.len()].copy_from_slice(program);
+        ram.0[RESET_VECTOR as usize + 1] = 0x02;
+        let mut cpu = Cpu65C02::new(ram);
+        cpu.reset();
+        cpu
+    }
+
+    /* Runs n instructions and returns the cycles taken by the last one. */
+    fn steps(cpu: &mut Cpu65C02<Ram>, n: usize) -> u32 {
+        let mut cycles = 0;
+        for _ in 0..n {
+            cycles = cpu.step();
+        }
+        cycles
+    }
+
+    #[test]
+    fn bit_immediate_only_touches_z() {
+        /* LDA #$01; BIT #$C0; BIT #$01 */
+        let mut cpu = cpu(&[0xA9, 0x01, 0x89, 0xC0, 0x89, 0x01]);
+        steps(&mut cpu, 1);
+        cpu.p |= FLAG_N | FLAG_V;
+        assert_eq!(steps(&mut cpu, 1), 2);
+        assert_eq!(cpu.p & (FLAG_N | FLAG_V | FLAG_Z), FLAG_N | FLAG_V | FLAG_Z);
+        cpu.p &= !(FLAG_N | FLAG_V);
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.p & (FLAG_N | FLAG_V | FLAG_Z), 0);
+    }
+
+    #[test]
+    fn jmp_indirect_crosses_page() {
+        /* JMP ($10FF) */
+        let mut cpu = cpu(&[0x6C, 0xFF, 0x10]);
+        cpu.memory.0[0x10FF] = 0x34;
+        cpu.memory.0[0x1100] = 0x12;
+        cpu.memory.0[0x1000] = 0x56;
+        assert_eq!(steps(&mut cpu, 1), 6);
+        assert_eq!(cpu.pc, 0x1234);
+    }
+
+    #[test]
+    fn decimal_adc_flags_and_cycles() {
+        /* SED; CLC; LDA #$99; ADC #$01; CLC; LDA #$79; ADC #$01; CLD; ADC #$01 */
+        let mut cpu = cpu(&[
+            0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01, 0x18, 0xA9, 0x79, 0x69, 0x01, 0xD8, 0x69, 0x01,
+        ]);
+        assert_eq!(steps(&mut cpu, 4), 3);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(cpu.p & (FLAG_N | FLAG_Z | FLAG_C), FLAG_Z | FLAG_C);
+
+        assert_eq!(steps(&mut cpu, 3), 3);
+        assert_eq!(cpu.a, 0x80);
+        assert_eq!(cpu.p & (FLAG_N | FLAG_Z | FLAG_C), FLAG_N);
+
+        assert_eq!(steps(&mut cpu, 2), 2);
+        assert_eq!(cpu.a, 0x81);
+    }
+
+    #[test]
+    fn decimal_sbc_flags_and_cycles() {
+        /* SED; SEC; LDA #$00; SBC #$01; SEC; LDA #$01; SBC #$01 */
+        let mut cpu = cpu(&[0xF8, 0x38, 0xA9, 0x00, 0xE9, 0x01, 0x38, 0xA9, 0x01, 0xE9, 0x01]);
+        assert_eq!(steps(&mut cpu, 4), 3);
+        assert_eq!(cpu.a, 0x99);
+        assert_eq!(cpu.p & (FLAG_N | FLAG_Z | FLAG_C), FLAG_N);
+
+        assert_eq!(steps(&mut cpu, 3), 3);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(cpu.p & (FLAG_N | FLAG_Z | FLAG_C), FLAG_Z | FLAG_C);
+    }
+
+    #[test]
+    fn tsb_and_trb() {
+        /* LDA #$0F; TSB $10; TRB $10; TRB $1234 */
+        let mut cpu = cpu(&[0xA9, 0x0F, 0x04, 0x10, 0x14, 0x10, 0x1C, 0x34, 0x12]);
+        cpu.memory.0[0x10] = 0xF0;
+        cpu.memory.0[0x1234] = 0x3C;
+        assert_eq!(steps(&mut cpu, 2), 5);
+        assert_eq!(cpu.memory.0[0x10], 0xFF);
+        assert!(cpu.p & FLAG_Z != 0);
+
+        assert_eq!(steps(&mut cpu, 1), 5);
+        assert_eq!(cpu.memory.0[0x10], 0xF0);
+        assert!(cpu.p & FLAG_Z == 0);
+
+        assert_eq!(steps(&mut cpu, 1), 6);
+        assert_eq!(cpu.memory.0[0x1234], 0x30);
+        assert!(cpu.p & FLAG_Z == 0);
+        assert_eq!(cpu.a, 0x0F);
+    }
+
+    #[test]
+    fn stz() {
+        /* LDX #$01; STZ $10; STZ $10,X; STZ $1234; STZ $1234,X */
+        let mut cpu = cpu(&[0xA2, 0x01, 0x64, 0x10, 0x74, 0x10, 0x9C, 0x34, 0x12, 0x9E, 0x34, 0x12]);
+        for addr in &[0x10, 0x11, 0x1234, 0x1235] {
+            cpu.memory.0[*addr] = 0x55;
+        }
+        let cycles: Vec<u32> = (0..5).map(|_| cpu.step()).collect();
+        assert_eq!(cycles, vec![2, 3, 4, 4, 5]);
+        for addr in &[0x10, 0x11, 0x1234, 0x1235] {
+            assert_eq!(cpu.memory.0[*addr], 0);
+        }
+    }
+
+    #[test]
+    fn rockwell_bit_opcodes_are_nops() {
+        /* RMB0, SMB0, BBR0 and BBS0 on the Rockwell part */
+        let mut cpu = cpu(&[0x07, 0x87, 0x0F, 0x8F]);
+        cpu.memory.0[0x10] = 0x55;
+        for pc in 0x201..0x205 {
+            assert_eq!(steps(&mut cpu, 1), 1);
+            assert_eq!(cpu.pc, pc);
+        }
+        assert_eq!(cpu.memory.0[0x10], 0x55);
+    }
+}
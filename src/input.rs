@@ -1,18 +1,33 @@
-use sdl2::EventPump;
+use sdl2::{EventPump, JoystickSubsystem};
 use sdl2::event::Event;
+use sdl2::joystick::Joystick;
 use sdl2::keyboard::KeyboardUtil;
 use sdl2::keyboard::{self, Keycode};
+use sdl2::mouse::MouseButton;
+
+/* The Apple has four paddles, which joystick axes stand in for. */
+const PADDLES: usize = 4;
 
 pub struct Input {
     events: EventPump,
     keyboard: KeyboardUtil,
+    /* kept open so their events keep coming */
+    _joysticks: Vec<Joystick>,
 }
 
 impl Input {
-    pub fn new(events: EventPump, keyboard: KeyboardUtil) -> Input {
+    pub fn new(events: EventPump, keyboard: KeyboardUtil, joystick: JoystickSubsystem) -> Input {
+        let count = joystick.num_joysticks().unwrap_or(0);
+        let joysticks = (0..count)
+            .filter_map(|index| joystick.open(index).ok())
+            .collect::<Vec<_>>();
+        for stick in joysticks.iter() {
+            info!("Using joystick {}", stick.name());
+        }
         Input {
             events: events,
             keyboard: keyboard,
+            _joysticks: joysticks,
         }
     }
 
@@ -156,10 +171,62 @@ impl<'a> Iterator for KeyboardInputs<'a> {
             if let Some(event) = self.input.events.poll_event() {
                 match event {
                     Event::Quit { .. } => return Some(KeyboardInput::Quit),
+                    /* the alt keys stand in for the open and closed apple buttons */
+                    Event::KeyDown { keycode: Some(Keycode::LAlt), .. } => {
+                        return Some(KeyboardInput::Button(0, true));
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::RAlt), .. } => {
+                        return Some(KeyboardInput::Button(1, true));
+                    }
+                    Event::KeyUp { keycode: Some(Keycode::LAlt), .. } => {
+                        return Some(KeyboardInput::Button(0, false));
+                    }
+                    Event::KeyUp { keycode: Some(Keycode::RAlt), .. } => {
+                        return Some(KeyboardInput::Button(1, false));
+                    }
+                    /* the first axes are the paddles, turned from 0 to 255 */
+                    Event::JoyAxisMotion { axis_idx, value, .. }
+                        if (axis_idx as usize) < PADDLES => {
+                        let turned = ((value as i32 + 0x8000) >> 8) as u8;
+                        return Some(KeyboardInput::Paddle(axis_idx as usize, turned));
+                    }
+                    Event::JoyButtonDown { button_idx, .. } if button_idx < 2 => {
+                        return Some(KeyboardInput::Button(button_idx as usize, true));
+                    }
+                    Event::JoyButtonUp { button_idx, .. } if button_idx < 2 => {
+                        return Some(KeyboardInput::Button(button_idx as usize, false));
+                    }
+                    Event::MouseMotion { xrel, yrel, .. } => {
+                        return Some(KeyboardInput::MouseMove(xrel, yrel));
+                    }
+                    Event::MouseButtonDown { mouse_btn: MouseButton::Left, .. } => {
+                        return Some(KeyboardInput::MouseButton(true));
+                    }
+                    Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                        return Some(KeyboardInput::MouseButton(false));
+                    }
                     Event::KeyDown { keycode, .. } => {
-                        if keycode == Some(Keycode::F2) {
+                        let mods = self.input.keyboard.mod_state();
+                        let ctrl = mods.intersects(keyboard::LCTRLMOD | keyboard::RCTRLMOD);
+                        let shift = mods.intersects(keyboard::LSHIFTMOD | keyboard::RSHIFTMOD);
+                        if keycode == Some(Keycode::F2) && ctrl {
+                            return Some(KeyboardInput::ForceReboot);
+                        }
+                        else if keycode == Some(Keycode::F2) {
                             return Some(KeyboardInput::Reset);
                         }
+                        else if keycode == Some(Keycode::F3) {
+                            return Some(KeyboardInput::PowerCycle);
+                        }
+                        else if keycode == Some(Keycode::F4) {
+                            return Some(KeyboardInput::CycleVideo);
+                        }
+                        else if keycode == Some(Keycode::F5) {
+                            return Some(KeyboardInput::Screenshot(shift, ctrl));
+                        }
+                        else if keycode == Some(Keycode::F6) {
+                            return Some(KeyboardInput::Record(shift));
+                        }
                         else if keycode == Some(Keycode::F1) {
                             return Some(KeyboardInput::Pause);
                         }
@@ -180,6 +247,20 @@ impl<'a> Iterator for KeyboardInputs<'a> {
 pub enum KeyboardInput {
     Quit,
     Reset,
+    /* Ctrl-Open-Apple-Reset */
+    ForceReboot,
+    PowerCycle,
     Key(u8),
+    Button(usize, bool),
+    Paddle(usize, u8),
+    MouseMove(i32, i32),
+    MouseButton(bool),
     Pause,
+    CycleVideo,
+    /* Shift for the window's size rather than the Apple's, Ctrl for the
+     * current video mode rather than plain colors
+     */
+    Screenshot(bool, bool),
+    /* start or stop recording, true for a raw stream rather than a clip */
+    Record(bool),
 }
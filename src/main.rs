@@ -3,36 +3,327 @@ extern crate sdl2;
 #[macro_use]
 extern crate log;
 extern crate env_logger;
+extern crate getopts;
 
 mod appleii;
+mod char_rom;
 mod monitor;
 mod input;
 mod mapper;
 mod peripheral_card;
+mod config;
+mod model;
+mod cpu;
+mod cpu65c02;
+mod mouse;
+mod z80;
+mod scanner;
+mod rom;
+mod ntsc;
+mod palette;
+mod ram_init;
+mod video_output;
+mod png;
+mod recorder;
 
-use mapper::ROM_SIZE;
+use config::{MachineConfig, ConfigError, NUM_SLOTS};
+use model::Model;
+use rom::RomMatch;
+use monitor::{RenderMode, ShotSize};
+use palette::{Palette, DEFAULT_PALETTE};
+use recorder::Format;
+use video_output::Headless;
+
+use getopts::Options;
 
 use std::env;
 use std::fs;
-use std::io::Read;
+use std::io::{self, Read, Write};
+use std::process;
+
+fn fail(msg: &str) -> ! {
+    let _ = writeln!(io::stderr(), "error: {}", msg);
+    process::exit(1);
+}
+
+fn warn_user(msg: &str) {
+    let _ = writeln!(io::stderr(), "warning: {}", msg);
+}
+
+fn read_rom(path: &str) -> Result<Vec<u8>, ConfigError> {
+    let mut rom = Vec::new();
+    try!(fs::File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut rom))
+        .map_err(|err| ConfigError::Io(path.to_string(), err)));
+    Ok(rom)
+}
+
+/* Reports what the rom is and settles the model from it when the
+ * user didn't pick one.
+ */
+fn identify_rom(rom: &[u8], config: &mut MachineConfig) {
+    let info = rom::identify(rom);
+    match info.found {
+        RomMatch::Known(name, model) => {
+            let _ = writeln!(io::stderr(),
+                             "rom: {} for a {} (crc32 {:08x})",
+                             name,
+                             model.cpu().name(),
+                             info.crc32);
+        }
+        RomMatch::Signature(name, _) => {
+            warn_user(&format!("rom (crc32 {:08x}) has the identification bytes of the {} but \
+                                is not a known dump; it may be patched or a clone",
+                               info.crc32,
+                               name));
+        }
+        RomMatch::Patched(model) => {
+            warn_user(&format!("rom (crc32 {:08x}) has {} family identification bytes but \
+                                does not match a known rom; it may be patched or a clone",
+                               info.crc32,
+                               model.name()));
+        }
+        RomMatch::Unknown => {
+            warn_user(&format!("unknown rom (crc32 {:08x})", info.crc32));
+        }
+    }
+
+    match (config.model, info.model()) {
+        (None, Some(found)) => config.model = Some(found),
+        (None, None) => {
+            /* go by size, which at least gets the memory map right */
+            let guess = if rom.len() == Model::AppleIIPlus.rom_size() {
+                Model::AppleIIPlus
+            } else {
+                Model::AppleIIe
+            };
+            warn_user(&format!("cannot tell the model from the rom, assuming {}", guess.name()));
+            config.model = Some(guess);
+        }
+        (Some(chosen), Some(found)) if chosen != found => {
+            warn_user(&format!("rom looks like it is for the {}, running it as the {} anyway",
+                               found.name(),
+                               chosen.name()));
+        }
+        _ => {}
+    }
+
+    let model = config.model();
+    let _ = writeln!(io::stderr(),
+                     "model: {} with a {}",
+                     model.name(),
+                     model.cpu().name());
+}
+
+fn start_recording(apple: &mut appleii::AppleII, path: &str, format: Format) {
+    if let Err(err) = apple.start_recording(path, format) {
+        fail(&format!("cannot record to {}: {}", path, err));
+    }
+}
+
+fn load_config(config_path: Option<String>,
+               model_arg: Option<String>,
+               ram_arg: Option<String>,
+               ram_init_arg: Option<String>,
+               rom_arg: Option<String>,
+               char_rom_arg: Option<String>,
+               slot_args: Vec<String>)
+               -> Result<(MachineConfig, Vec<u8>), ConfigError> {
+    let model = match model_arg {
+        Some(name) => Some(try!(config::parse_model(&name))),
+        None => None,
+    };
+
+    let (mut config, has_layout) = match config_path {
+        Some(path) => (try!(MachineConfig::from_file(&path)), true),
+        None => (MachineConfig::new(), false),
+    };
+    if model.is_some() {
+        config.model = model;
+    }
+    if let Some(size) = ram_arg {
+        config.ram = Some(try!(config::parse_ram(&size)));
+    }
+    if let Some(spec) = ram_init_arg {
+        config.ram_init = Some(try!(config::parse_ram_init(&spec)));
+    }
+    if rom_arg.is_some() {
+        config.rom = rom_arg;
+    }
+    if char_rom_arg.is_some() {
+        config.char_rom = char_rom_arg;
+    }
+
+    let rom_path = match config.rom {
+        Some(ref path) => path.clone(),
+        None => return Err(ConfigError::Syntax("no rom file specified".to_string())),
+    };
+    let rom = try!(read_rom(&rom_path));
+    identify_rom(&rom, &mut config);
+
+    /* the default layout depends on the model, which can come from the rom */
+    if !has_layout {
+        config.slots = MachineConfig::default_layout(config.model()).slots;
+    }
+
+    /* --slot replaces whatever the file (or default layout) put in that slot,
+     * but giving the same slot twice on the command line is a conflict.
+     */
+    let mut overrides = MachineConfig::new();
+    for arg in slot_args.iter() {
+        let (slot, card) = try!(MachineConfig::parse_slot_arg(arg));
+        try!(overrides.set_slot(slot, card));
+    }
+    for slot in 0..NUM_SLOTS {
+        if let Some(card) = overrides.slots[slot].take() {
+            config.slots[slot] = Some(card);
+        }
+    }
+
+    try!(config.validate());
+
+    let rom_size = config.model().rom_size();
+    if rom.len() != rom_size {
+        return Err(ConfigError::FileSize(rom_path, rom_size, rom.len()));
+    }
+
+    Ok((config, rom))
+}
 
 fn main() {
     env_logger::init().unwrap();
-    let filename = env::args().nth(1).expect("No file specified.");
 
-    let mut file = fs::File::open(filename).expect("File not found.");
-    let file_size = file.metadata().expect("Could not get metadata").len();
+    let args: Vec<String> = env::args().collect();
+    let mut opts = Options::new();
+    opts.optopt("c", "config", "machine configuration file", "FILE");
+    opts.optopt("m", "model", "machine to emulate: ii, iiplus, iie, iieenh or iic", "MODEL");
+    opts.optopt("r", "ram", "motherboard RAM in K for the ii and iiplus, 4 to 48", "SIZE");
+    opts.optopt("",
+                "ram-init",
+                "power on RAM contents: zero, pattern, random or random:SEED",
+                "INIT");
+    opts.optopt("",
+                "charrom",
+                "character generator rom, 2K or a 4K //e video rom (default built in)",
+                "FILE");
+    opts.optmulti("s",
+                  "slot",
+                  "put a card in a slot, e.g. 6=disk2,drive1=dos33.dsk",
+                  "SLOT=CARD[,KEY=VALUE...]");
+    opts.optopt("",
+                "video",
+                "how to draw the screen: simple, ntsc, or green, amber or white \
+                 monochrome (default simple, F4 cycles)",
+                "MODE");
+    opts.optopt("",
+                "palette",
+                "lores and hires colors for simple video: emulator, ntsc, iigs or a palette \
+                 file (default emulator)",
+                "PALETTE");
+    opts.optopt("",
+                "headless",
+                "run for FRAMES frames without a window, then print the CRC-32 of the \
+                 last one",
+                "FRAMES");
+    opts.optopt("",
+                "screenshot",
+                "with --headless, save the last frame to FILE as a .png in the chosen video \
+                 mode (F5 takes one, Shift for window size, Ctrl for the current mode)",
+                "FILE");
+    opts.optopt("",
+                "record",
+                "record every frame from the start to FILE: an animated .png, or a raw .y4m \
+                 with the speaker in a .wav beside it (F6 and Shift+F6 toggle these)",
+                "FILE");
+    opts.optflag("h", "help", "print this help");
 
-    if file_size != ROM_SIZE as u64 {
-        panic!("File not the proper size. Found {} bytes, should be {} bytes.",
-               file_size,
-               ROM_SIZE);
+    let matches = match opts.parse(&args[1..]) {
+        Ok(matches) => matches,
+        Err(err) => fail(&err.to_string()),
+    };
+    if matches.opt_present("h") {
+        let brief = format!("Usage: {} [options] [ROM]", args[0]);
+        print!("{}", opts.usage(&brief));
+        return;
     }
 
-    let mut buf = [0x00; ROM_SIZE];
-    file.read_exact(&mut buf).expect("Could not read from file.");
+    let (config, rom) = match load_config(matches.opt_str("c"),
+                                          matches.opt_str("m"),
+                                          matches.opt_str("r"),
+                                          matches.opt_str("ram-init"),
+                                          matches.free.get(0).cloned(),
+                                          matches.opt_str("charrom"),
+                                          matches.opt_strs("s")) {
+        Ok(loaded) => loaded,
+        Err(err) => fail(&err.to_string()),
+    };
+
+    let render_mode = match matches.opt_str("video") {
+        Some(name) => {
+            match RenderMode::from_name(&name) {
+                Some(mode) => mode,
+                None => {
+                    fail(&format!("unknown video mode \"{}\", use simple, ntsc, green, amber \
+                                   or white",
+                                  name))
+                }
+            }
+        }
+        None => RenderMode::Simple,
+    };
+
+    let palette_spec = matches.opt_str("palette").unwrap_or(DEFAULT_PALETTE.to_string());
+    let palette = match Palette::from_spec(&palette_spec) {
+        Ok(palette) => palette,
+        Err(err) => fail(&err.to_string()),
+    };
 
-    let mut sdl_apple = appleii::AppleII::new(buf);
+    let record = matches.opt_str("record").map(|path| {
+        match Format::from_path(&path) {
+            Some(format) => (path, format),
+            None => fail(&format!("cannot tell how to record to \"{}\", use .png or .y4m", path)),
+        }
+    });
+
+    if let Some(frames) = matches.opt_str("headless") {
+        let frames = match frames.parse::<u64>() {
+            Ok(frames) => frames,
+            Err(_) => fail(&format!("invalid frame count \"{}\"", frames)),
+        };
+        let output = Headless::new();
+        let shown = output.shown();
+        let apple = appleii::AppleII::headless(rom,
+                                               &config,
+                                               Box::new(output),
+                                               render_mode,
+                                               palette);
+        let mut apple = match apple {
+            Ok(apple) => apple,
+            Err(err) => fail(&err.to_string()),
+        };
+        if let Some((ref path, format)) = record {
+            start_recording(&mut apple, path, format);
+        }
+        apple.run_frames(frames);
+        apple.stop_recording();
+        if let Some(path) = matches.opt_str("screenshot") {
+            if let Err(err) = apple.screenshot(&path, ShotSize::Native, true) {
+                fail(&format!("cannot save a screenshot to {}: {}", path, err));
+            }
+        }
+
+        let shown = shown.borrow();
+        println!("frame {} crc32 {:08x}", shown.frames, rom::crc32(&shown.pixels));
+        return;
+    }
+
+    let mut sdl_apple = match appleii::AppleII::new(rom, &config, render_mode, palette) {
+        Ok(apple) => apple,
+        Err(err) => fail(&err.to_string()),
+    };
+    if let Some((ref path, format)) = record {
+        start_recording(&mut sdl_apple, path, format);
+    }
 
     sdl_apple.run();
 }
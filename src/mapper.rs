@@ -1,42 +1,192 @@
 use r6502::memory::Memory;
 use peripheral_card::PeripheralCard;
+use scanner;
+use model::Model;
+use mouse::Mouse;
+use ram_init::RamInit;
 
 pub const RAM_SIZE: usize = 0xC000;
-pub const ROM_SIZE: usize = 0x3000;
 
 pub const APPLE_II_TEXT_WIDTH: usize = 40;
-pub const APPLE_II_TEXT_HEIGHT: usize = 24;
 
+#[derive(Clone, Copy, PartialEq)]
 pub struct ScreenState {
     pub graphics: bool,
     pub all: bool,
     pub primary: bool,
     pub low_res: bool,
+    /* //e only */
+    pub store80: bool,
+    pub col80: bool,
+    pub altcharset: bool,
+}
+
+impl ScreenState {
+    pub fn new() -> ScreenState {
+        ScreenState {
+            graphics: false,
+            all: true,
+            primary: true,
+            low_res: true,
+            store80: false,
+            col80: false,
+            altcharset: false,
+        }
+    }
+
+    /* With 80STORE on, PAGE2 switches between main and aux memory
+     * rather than picking the second display page.
+     */
+    pub fn page2(&self) -> bool {
+        !self.primary && !self.store80
+    }
+
+    /* Where text and lores are shown from, mixed mode text included. */
+    pub fn text_page(&self) -> usize {
+        if self.page2() { 0x800 } else { 0x400 }
+    }
+
+    pub fn hires_page(&self) -> usize {
+        if self.page2() { 0x4000 } else { 0x2000 }
+    }
+}
+
+/* //e MMU switches */
+pub struct MmuState {
+    pub ramrd: bool,
+    pub ramwrt: bool,
+    pub intcxrom: bool,
+    pub altzp: bool,
+    pub slotc3rom: bool,
+    /* internal rom at $C800-$CFFF, set by touching the internal $C3 rom */
+    pub intc8rom: bool,
+    /* //c: $C058-$C05F go to the annunciators instead of the mouse */
+    pub ioudis: bool,
+}
+
+impl MmuState {
+    pub fn new() -> MmuState {
+        MmuState {
+            ramrd: false,
+            ramwrt: false,
+            intcxrom: false,
+            altzp: false,
+            slotc3rom: false,
+            intc8rom: false,
+            ioudis: false,
+        }
+    }
+}
+
+/* Paddle timers run out after about 11 cycles per unit of paddle value. */
+const PADDLE_CYCLES_PER_UNIT: u64 = 11;
+
+/* The game I/O connector: pushbuttons, paddles and cassette input. */
+pub struct GameIo {
+    pub buttons: [bool; 3],
+    pub paddles: [u8; 4],
+    pub cassette_in: bool,
+    paddle_trigger: u64,
+}
+
+impl GameIo {
+    pub fn new() -> GameIo {
+        GameIo {
+            buttons: [false; 3],
+            /* nothing plugged in reads as fully turned */
+            paddles: [0xFF; 4],
+            cassette_in: false,
+            paddle_trigger: 0,
+        }
+    }
+
+    fn trigger_paddles(&mut self, cycles: u64) {
+        self.paddle_trigger = cycles;
+    }
+
+    /* $C060-$C06F, repeating every 8 bytes. Only bit 7 is driven. */
+    fn read(&self, addr: u16, cycles: u64) -> u8 {
+        let high = match addr & 0x7 {
+            0x0 => self.cassette_in,
+            0x1...0x3 => self.buttons[(addr & 0x7) as usize - 1],
+            _ => {
+                let paddle = self.paddles[(addr & 0x3) as usize] as u64;
+                cycles.wrapping_sub(self.paddle_trigger) < paddle * PADDLE_CYCLES_PER_UNIT
+            }
+        };
+        if high { 0x80 } else { 0x00 }
+    }
 }
 
 pub struct Mapper<'a> {
+    pub model: Model,
     pub ram: [u8; RAM_SIZE],
-    pub rom: [u8; ROM_SIZE],
+    /* RAM above this isn't populated, so reads float and writes are lost */
+    pub ram_size: usize,
+    pub ram_init: RamInit,
+    /* empty on models without auxiliary memory */
+    pub aux_ram: Vec<u8>,
+    /* covers $D000-$FFFF, or $C000-$FFFF on the //e */
+    pub rom: Vec<u8>,
     pub key: u8,
     pub screen: ScreenState,
+    pub mmu: MmuState,
+    pub mouse: Mouse,
+    pub annunciators: [bool; 4],
+    pub speaker: bool,
+    pub cassette_out: bool,
+    pub game_io: GameIo,
+    /* cpu cycle count at the start of the current instruction */
+    pub cycles: u64,
+    /* bus accesses so far in the current instruction, one per cycle */
+    accesses: u64,
+    /* the cycle of the access under way */
+    bus_cycle: u64,
+    /* the screen as it was before each display switch flipped during
+     * the current instruction, with the cycle it flipped on
+     */
+    screen_changes: Vec<(u64, ScreenState)>,
     pub cards: [Option<Box<PeripheralCard + 'a>>; 8],
     pub has_lang_card: bool,
+    /* slot currently owning the $C800-$CFFF expansion rom space */
+    pub expansion_slot: Option<usize>,
 }
 
 impl<'a> Mapper<'a> {
-    pub fn new(rom: [u8; ROM_SIZE]) -> Mapper<'a> {
+    pub fn new(model: Model, rom: Vec<u8>, ram_size: usize, ram_init: RamInit) -> Mapper<'a> {
+        assert_eq!(rom.len(), model.rom_size());
+        assert!(ram_size <= RAM_SIZE);
+        let mut ram = [0x00; RAM_SIZE];
+        ram_init.fill(&mut ram, 0);
+        let mut aux_ram = if model.has_aux_memory() {
+            vec![0x00; RAM_SIZE]
+        } else {
+            Vec::new()
+        };
+        ram_init.fill(&mut aux_ram, 1);
+
         Mapper {
-            ram: [0x00; RAM_SIZE],
+            model: model,
+            ram: ram,
+            ram_size: ram_size,
+            ram_init: ram_init,
+            aux_ram: aux_ram,
             rom: rom,
             key: 0,
-            screen: ScreenState {
-                graphics: false,
-                all: true,
-                primary: true,
-                low_res: true,
-            },
+            screen: ScreenState::new(),
+            mmu: MmuState::new(),
+            mouse: Mouse::new(),
+            annunciators: [false; 4],
+            speaker: false,
+            cassette_out: false,
+            game_io: GameIo::new(),
+            cycles: 0,
+            accesses: 0,
+            bus_cycle: 0,
+            screen_changes: Vec::new(),
             cards: [None, None, None, None, None, None, None, None],
             has_lang_card: false,
+            expansion_slot: None,
         }
     }
 
@@ -44,6 +194,14 @@ impl<'a> Mapper<'a> {
         self.key = key;
     }
 
+    pub fn set_button(&mut self, button: usize, pressed: bool) {
+        self.game_io.buttons[button] = pressed;
+    }
+
+    pub fn set_paddle(&mut self, paddle: usize, val: u8) {
+        self.game_io.paddles[paddle] = val;
+    }
+
     pub fn add_card<T: PeripheralCard + 'a>(&mut self, card: T, slot: usize) {
         if slot == 0 {
             self.has_lang_card = card.is_language_card();
@@ -57,65 +215,415 @@ impl<'a> Mapper<'a> {
             self.has_lang_card = false;
         }
 
+        if self.expansion_slot == Some(slot) {
+            self.expansion_slot = None;
+        }
+
         self.cards[slot] = None;
     }
+
+    pub fn move_mouse(&mut self, dx: i32, dy: i32) {
+        self.mouse.move_by(dx, dy);
+    }
+
+    pub fn set_mouse_button(&mut self, pressed: bool) {
+        self.mouse.button = pressed;
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        for card in self.cards.iter_mut() {
+            if let Some(ref mut card) = *card {
+                card.tick(cycles);
+            }
+        }
+        if !self.model.has_slots() {
+            self.mouse.tick(cycles, self.cycles + cycles);
+        }
+    }
+
+    /* The IRQ and NMI lines are wire-ORed across all slots. */
+    pub fn irq(&self) -> bool {
+        (!self.model.has_slots() && self.mouse.irq()) ||
+        self.cards.iter().any(|card| card.as_ref().map_or(false, |card| card.irq()))
+    }
+
+    pub fn nmi(&self) -> bool {
+        self.cards.iter().any(|card| card.as_ref().map_or(false, |card| card.nmi()))
+    }
+
+    pub fn reset(&mut self) {
+        self.expansion_slot = None;
+        if self.model.has_aux_memory() {
+            self.mmu = MmuState::new();
+            self.screen.store80 = false;
+            self.screen.col80 = false;
+            self.screen.altcharset = false;
+            if let Some(ref mut card) = self.cards[0] {
+                card.set_aux_bank(false);
+            }
+        }
+        if !self.model.has_slots() {
+            self.mouse.reset();
+        }
+        for card in self.cards.iter_mut() {
+            if let Some(ref mut card) = *card {
+                card.reset();
+            }
+        }
+    }
+
+    /* A power cycle: RAM gets its power-on contents again and every
+     * switch and card starts over. The game port inputs are left alone
+     * since they are whatever the user is holding.
+     */
+    pub fn power_on(&mut self) {
+        let init = self.ram_init;
+        init.fill(&mut self.ram, 0);
+        init.fill(&mut self.aux_ram, 1);
+        self.key = 0;
+        self.screen = ScreenState::new();
+        self.mmu = MmuState::new();
+        self.mouse.reset();
+        self.annunciators = [false; 4];
+        self.speaker = false;
+        self.cassette_out = false;
+        self.expansion_slot = None;
+        for card in self.cards.iter_mut() {
+            if let Some(ref mut card) = *card {
+                card.power_on();
+            }
+        }
+    }
+
+    pub fn bus_master(&self) -> Option<usize> {
+        self.cards.iter().position(|card| card.as_ref().map_or(false, |card| card.bus_request()))
+    }
+
+    pub fn run_bus_master(&mut self, slot: usize, cycles: u64) -> u64 {
+        /* the card is lifted out of its slot while it drives the bus */
+        let mut card = self.cards[slot].take().unwrap();
+        let taken = card.run_bus_master(self, cycles);
+        self.cards[slot] = Some(card);
+        taken
+    }
+
+    /* Called before each instruction with the cycle it starts on. */
+    pub fn start_instruction(&mut self, cycles: u64) {
+        self.cycles = cycles;
+        self.accesses = 0;
+        self.bus_cycle = cycles;
+        self.screen_changes.clear();
+    }
+
+    /* The display switch changes made by the instruction so far, for
+     * the beam to catch up to before showing the new state.
+     */
+    pub fn take_screen_changes(&mut self) -> Vec<(u64, ScreenState)> {
+        self.screen_changes.drain(..).collect()
+    }
+
+    fn screen_changed(&mut self, before: ScreenState) {
+        if self.screen != before {
+            self.screen_changes.push((self.bus_cycle, before));
+        }
+    }
+
+    /* The 6502 touches the bus on every cycle, so the nth access of an
+     * instruction happens n - 1 cycles after it started.
+     */
+    fn bus_access(&mut self) {
+        self.bus_cycle = self.cycles + self.accesses;
+        self.accesses += 1;
+    }
+
+    /* Reads that nothing drives see whatever the video circuitry
+     * left on the data bus on that very cycle.
+     */
+    pub fn floating_bus(&self) -> u8 {
+        self.ram[scanner::address(self.bus_cycle, &self.screen) as usize]
+    }
+
+    fn rom_byte(&self, addr: u16) -> u8 {
+        self.rom[addr as usize + self.rom.len() - 0x10000]
+    }
+
+    /* Whether $0000-$BFFF goes to aux memory on the //e. */
+    fn aux_bank(&self, addr: u16, write: bool) -> bool {
+        match addr {
+            0x0000...0x01FF => self.mmu.altzp,
+            0x0400...0x07FF if self.screen.store80 => !self.screen.primary,
+            0x2000...0x3FFF if self.screen.store80 && !self.screen.low_res => !self.screen.primary,
+            _ => if write { self.mmu.ramwrt } else { self.mmu.ramrd },
+        }
+    }
+
+    /* Whether the //e's own rom answers for a slot's $Cn00 page. */
+    fn internal_slot_rom(&self, slot: usize) -> bool {
+        if !self.model.has_slots() {
+            return true;
+        }
+        self.model.has_aux_memory() && (self.mmu.intcxrom || (slot == 3 && !self.mmu.slotc3rom))
+    }
+
+    fn internal_expansion_rom(&self) -> bool {
+        !self.model.has_slots() || self.mmu.intcxrom || self.mmu.intc8rom
+    }
+
+    /* //e $C000-$C00F writes: each pair turns a switch off then on. */
+    fn set_mmu_switch(&mut self, addr: u16) {
+        let on = addr & 0x1 != 0;
+        let before = self.screen;
+        match (addr >> 1) & 0x7 {
+            0 => self.screen.store80 = on,
+            1 => self.mmu.ramrd = on,
+            2 => self.mmu.ramwrt = on,
+            3 => self.mmu.intcxrom = on,
+            4 => {
+                self.mmu.altzp = on;
+                /* ALTZP also swaps the language card RAM for its aux copy */
+                if let Some(ref mut card) = self.cards[0] {
+                    card.set_aux_bank(on);
+                }
+            }
+            5 => self.mmu.slotc3rom = on,
+            6 => self.screen.col80 = on,
+            _ => self.screen.altcharset = on,
+        }
+        self.screen_changed(before);
+    }
+
+    /* //e $C011-$C01F status reads, returned in bit 7. */
+    fn mmu_status(&self, addr: u16) -> bool {
+        let lang_card = self.cards[0].as_ref();
+        match addr & 0xF {
+            0x0 => self.key & 0x80 != 0,
+            0x1 => lang_card.map_or(false, |card| card.language_bank2()),
+            0x2 => lang_card.map_or(false, |card| card.language_read_ram()),
+            0x3 => self.mmu.ramrd,
+            0x4 => self.mmu.ramwrt,
+            /* the //c has no INTCXROM or SLOTC3ROM, these are its mouse interrupts */
+            0x5 if !self.model.has_slots() => self.mouse.x_int(),
+            0x5 => self.mmu.intcxrom,
+            0x6 => self.mmu.altzp,
+            0x7 if !self.model.has_slots() => self.mouse.y_int(),
+            0x7 => self.mmu.slotc3rom,
+            0x8 => self.screen.store80,
+            /* the //c reports its VBL interrupt here, others read low during blanking */
+            0x9 if !self.model.has_slots() => self.mouse.vbl_int,
+            0x9 => !scanner::in_vblank(self.bus_cycle),
+            0xA => !self.screen.graphics,
+            0xB => !self.screen.all,
+            0xC => !self.screen.primary,
+            0xD => !self.screen.low_res,
+            0xE => self.screen.altcharset,
+            _ => self.screen.col80,
+        }
+    }
+
+    /* $C050-$C05F: screen mode switches, then the annunciators where
+     * even addresses turn them off and odd addresses turn them on.
+     */
+    fn set_screen_switch(&mut self, addr: u16) {
+        let before = self.screen;
+        match addr & 0xF {
+            0x0 => self.screen.graphics = true,
+            0x1 => self.screen.graphics = false,
+            0x2 => self.screen.all = true,
+            0x3 => self.screen.all = false,
+            0x4 => self.screen.primary = true,
+            0x5 => self.screen.primary = false,
+            0x6 => self.screen.low_res = true,
+            0x7 => self.screen.low_res = false,
+            0x8...0xF if !self.model.has_slots() && !self.mmu.ioudis => self.set_mouse_switch(addr),
+            _ => self.annunciators[((addr >> 1) & 0x3) as usize] = addr & 0x1 != 0,
+        }
+        self.screen_changed(before);
+    }
+
+    /* //c $C058-$C05F with IOUDIS off: mouse and VBL interrupt control. */
+    fn set_mouse_switch(&mut self, addr: u16) {
+        let on = addr & 0x1 != 0;
+        match addr & 0xF {
+            0x8 | 0x9 => self.mouse.xy_int_enabled = on,
+            0xA | 0xB => self.mouse.vbl_int_enabled = on,
+            0xC | 0xD => self.mouse.set_x_edge(!on),
+            _ => self.mouse.set_y_edge(!on),
+        }
+    }
+
+    /* $C070 triggers the paddle timers, and on the //c also
+     * acknowledges the VBL interrupt.
+     */
+    fn trigger_paddles(&mut self, addr: u16) {
+        self.game_io.trigger_paddles(self.bus_cycle);
+        if !self.model.has_slots() && addr == 0xC070 {
+            self.mouse.vbl_int = false;
+        }
+    }
+
+    fn status_byte(&self, flag: bool) -> u8 {
+        let high = if flag { 0x80 } else { 0x00 };
+        high | (self.floating_bus() & 0x7F)
+    }
+
+    /* The //c's extra status reads in $C040-$C07F, returned in bit 7.
+     * Reading $C048 also clears the mouse interrupts.
+     */
+    fn iou_status(&mut self, addr: u16) -> Option<bool> {
+        if self.model.has_slots() {
+            return None;
+        }
+
+        match addr {
+            0xC040 => Some(self.mouse.xy_int_enabled),
+            0xC041 => Some(self.mouse.vbl_int_enabled),
+            0xC042 => Some(!self.mouse.x_rising()),
+            0xC043 => Some(!self.mouse.y_rising()),
+            0xC048 => {
+                self.mouse.clear_xy_int();
+                Some(false)
+            }
+            /* the mouse button reads low while pressed */
+            0xC063 => Some(!self.mouse.button),
+            0xC066 => Some(self.mouse.x1()),
+            0xC067 => Some(self.mouse.y1()),
+            0xC07E => Some(self.mmu.ioudis),
+            _ => None,
+        }
+    }
+
+    /* The first card with its own video output, if any. */
+    pub fn video_card(&self) -> Option<&(PeripheralCard + 'a)> {
+        self.cards
+            .iter()
+            .filter_map(|card| card.as_ref())
+            .find(|card| card.video_size().is_some())
+            .map(|card| &**card)
+    }
+
+    /* Any access to a card's $Cn00 page makes it the owner of the
+     * shared expansion rom space until $CFFF is referenced.
+     */
+    fn select_expansion_slot(&mut self, slot: usize) {
+        if self.cards[slot].is_some() {
+            self.expansion_slot = Some(slot);
+        }
+    }
 }
 
 impl<'a> Memory<u8> for Mapper<'a> {
     fn read_without_mm(&mut self, addr: u16) -> u8 {
+        self.bus_access();
         match addr {
-            0x0000...0xBFFF => self.ram[addr as usize],
-            0xC000 => self.key,
-            0xC010 => {
-                self.key &= 0x7F;
-                0x00
-            }
-            0xC050 => {
-                self.screen.graphics = true;
-                0x00
+            0x0000...0xBFFF => {
+                if self.aux_bank(addr, false) {
+                    self.aux_ram[addr as usize]
+                } else if (addr as usize) < self.ram_size {
+                    self.ram[addr as usize]
+                } else {
+                    self.floating_bus()
+                }
             }
-            0xC051 => {
-                self.screen.graphics = false;
-                0x00
+            0xC000...0xC00F => self.key,
+            0xC010...0xC01F => {
+                if self.model.has_aux_memory() {
+                    let status = if self.mmu_status(addr) { 0x80 } else { 0x00 };
+                    if addr == 0xC010 {
+                        self.key &= 0x7F;
+                    }
+                    status | (self.key & 0x7F)
+                } else {
+                    self.key &= 0x7F;
+                    self.floating_bus()
+                }
             }
-            0xC052 => {
-                self.screen.all = true;
-                0x00
+            0xC020...0xC02F => {
+                self.cassette_out = !self.cassette_out;
+                self.floating_bus()
             }
-            0xC053 => {
-                self.screen.all = false;
-                0x00
+            0xC030...0xC03F => {
+                self.speaker = !self.speaker;
+                self.floating_bus()
             }
-            0xC054 => {
-                self.screen.primary = true;
-                0x00
+            /* game I/O strobe, nothing is wired to it */
+            0xC040...0xC04F => {
+                match self.iou_status(addr) {
+                    Some(flag) => self.status_byte(flag),
+                    None => self.floating_bus(),
+                }
             }
-            0xC055 => {
-                self.screen.primary = false;
-                0x00
+            0xC050...0xC05F => {
+                self.set_screen_switch(addr);
+                self.floating_bus()
             }
-            0xC056 => {
-                self.screen.low_res = true;
-                0x00
+            0xC060...0xC06F => {
+                match self.iou_status(addr) {
+                    Some(flag) => self.status_byte(flag),
+                    None => self.game_io.read(addr, self.bus_cycle) | (self.floating_bus() & 0x7F),
+                }
             }
-            0xC057 => {
-                self.screen.low_res = false;
-                0x00
+            0xC070...0xC07F => {
+                self.trigger_paddles(addr);
+                match self.iou_status(addr) {
+                    Some(flag) => self.status_byte(flag),
+                    None => self.floating_bus(),
+                }
             }
             0xC080...0xC0FF => {
                 let slot = (((addr - 0xC000) >> 4) - 8) as usize;
                 match self.cards[slot] {
                     Some(ref mut card) => card.read_switch(addr & 0xF),
-                    None => 0xFF,
+                    None => self.floating_bus(),
                 }
             }
             0xC100...0xC7FF => {
                 let slot = ((addr - 0xC000) >> 8) as usize;
+                if self.internal_slot_rom(slot) {
+                    if slot == 3 {
+                        self.mmu.intc8rom = true;
+                    }
+                    return self.rom_byte(addr);
+                }
+
+                self.select_expansion_slot(slot);
                 match self.cards[slot] {
                     Some(ref mut card) => card.read_rom(addr),
-                    None => 0xFF,
+                    None => self.floating_bus(),
+                }
+            }
+            0xC800...0xCFFE => {
+                if self.internal_expansion_rom() {
+                    return self.rom_byte(addr);
+                }
+
+                match self.expansion_slot {
+                    Some(slot) => {
+                        self.cards[slot]
+                            .as_mut()
+                            .unwrap()
+                            .read_expansion_rom(addr)
+                    }
+                    None => self.floating_bus(),
                 }
             }
+            0xCFFF => {
+                let val = if self.internal_expansion_rom() {
+                    self.rom_byte(addr)
+                } else {
+                    match self.expansion_slot {
+                        Some(slot) => {
+                            self.cards[slot]
+                                .as_mut()
+                                .unwrap()
+                                .read_expansion_rom(addr)
+                        }
+                        None => self.floating_bus(),
+                    }
+                };
+                self.mmu.intc8rom = false;
+                self.expansion_slot = None;
+                val
+            }
             0xD000...0xFFFF => {
                 if self.has_lang_card {
                     self.cards
@@ -125,31 +633,86 @@ impl<'a> Memory<u8> for Mapper<'a> {
                         .unwrap()
                         .read_language_rom(addr)
                 } else {
-                    self.rom[(addr - 0xD000) as usize]
+                    self.rom_byte(addr)
                 }
             }
-            _ => 0x00,
         }
     }
 
     fn write_without_mm(&mut self, addr: u16, val: u8) {
+        self.bus_access();
         match addr {
-            0x0000...0xBFFF => self.ram[addr as usize] = val,
-            0xC010 => self.key &= 0x7F,
-            0xC050 => self.screen.graphics = true,
-            0xC051 => self.screen.graphics = false,
-            0xC052 => self.screen.all = true,
-            0xC053 => self.screen.all = false,
-            0xC054 => self.screen.primary = true,
-            0xC055 => self.screen.primary = false,
-            0xC056 => self.screen.low_res = true,
-            0xC057 => self.screen.low_res = false,
+            0x0000...0xBFFF => {
+                if self.aux_bank(addr, true) {
+                    self.aux_ram[addr as usize] = val;
+                } else if (addr as usize) < self.ram_size {
+                    self.ram[addr as usize] = val;
+                }
+            }
+            0xC000...0xC00F => {
+                if self.model.has_aux_memory() {
+                    self.set_mmu_switch(addr);
+                }
+            }
+            0xC010...0xC01F => self.key &= 0x7F,
+            0xC020...0xC02F => self.cassette_out = !self.cassette_out,
+            0xC030...0xC03F => self.speaker = !self.speaker,
+            0xC050...0xC05F => self.set_screen_switch(addr),
+            0xC048 if !self.model.has_slots() => self.mouse.clear_xy_int(),
+            0xC070...0xC07F => {
+                self.trigger_paddles(addr);
+                if !self.model.has_slots() {
+                    match addr {
+                        0xC07E => self.mmu.ioudis = true,
+                        0xC07F => self.mmu.ioudis = false,
+                        _ => {}
+                    }
+                }
+            }
             0xC080...0xC0FF => {
                 let slot = (((addr - 0xC000) >> 4) - 8) as usize;
                 if let Some(ref mut card) = self.cards[slot] {
                     card.write_switch(addr & 0xF, val);
                 }
             }
+            0xC100...0xC7FF => {
+                let slot = ((addr - 0xC000) >> 8) as usize;
+                if self.internal_slot_rom(slot) {
+                    if slot == 3 {
+                        self.mmu.intc8rom = true;
+                    }
+                    return;
+                }
+
+                self.select_expansion_slot(slot);
+                if let Some(ref mut card) = self.cards[slot] {
+                    card.write_rom(addr, val);
+                }
+            }
+            0xC800...0xCFFE => {
+                if self.internal_expansion_rom() {
+                    return;
+                }
+
+                if let Some(slot) = self.expansion_slot {
+                    self.cards[slot]
+                        .as_mut()
+                        .unwrap()
+                        .write_expansion_rom(addr, val);
+                }
+            }
+            0xCFFF => {
+                if !self.internal_expansion_rom() {
+                    if let Some(slot) = self.expansion_slot {
+                        self.cards[slot]
+                            .as_mut()
+                            .unwrap()
+                            .write_expansion_rom(addr, val);
+                    }
+                }
+                self.mmu.intc8rom = false;
+                self.expansion_slot = None;
+            }
             0xD000...0xFFFF => {
                 if self.has_lang_card {
                     self.cards
@@ -164,3 +727,32 @@ impl<'a> Memory<u8> for Mapper<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::Model;
+    use ram_init::RamInit;
+    use scanner::HBLANK_CYCLES;
+
+    #[test]
+    fn floating_bus_follows_the_access_cycle() {
+        let mut mapper = Mapper::new(Model::AppleIIPlus, vec![0; 0x3000], RAM_SIZE, RamInit::Zero);
+        for (offset, byte) in mapper.ram[0x400..0x428].iter_mut().enumerate() {
+            *byte = offset as u8;
+        }
+
+        /* LDA $C020 takes four cycles and reads the switch on the last */
+        mapper.start_instruction(HBLANK_CYCLES - 3);
+        for addr in 0..3 {
+            mapper.read(addr);
+        }
+        assert_eq!(mapper.read(0xC020), 0);
+
+        mapper.start_instruction(HBLANK_CYCLES + 7);
+        for addr in 0..3 {
+            mapper.read(addr);
+        }
+        assert_eq!(mapper.read(0xC020), 10);
+    }
+}
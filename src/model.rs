@@ -0,0 +1,87 @@
+use cpu::CpuKind;
+
+/* The machines that can be emulated. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    AppleII,
+    AppleIIPlus,
+    AppleIIe,
+    AppleIIeEnhanced,
+    AppleIIc,
+}
+
+impl Model {
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name {
+            "ii" => Some(Model::AppleII),
+            "iiplus" => Some(Model::AppleIIPlus),
+            "iie" => Some(Model::AppleIIe),
+            "iieenh" => Some(Model::AppleIIeEnhanced),
+            "iic" => Some(Model::AppleIIc),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Model::AppleII => "ii",
+            Model::AppleIIPlus => "iiplus",
+            Model::AppleIIe => "iie",
+            Model::AppleIIeEnhanced => "iieenh",
+            Model::AppleIIc => "iic",
+        }
+    }
+
+    /* The II and II+ roms cover $D000-$FFFF, the others $C000-$FFFF.
+     * The II's is Integer BASIC and the old monitor, without autostart.
+     */
+    pub fn rom_size(&self) -> usize {
+        match *self {
+            Model::AppleII | Model::AppleIIPlus => 0x3000,
+            _ => 0x4000,
+        }
+    }
+
+    pub fn cpu(&self) -> CpuKind {
+        match *self {
+            Model::AppleII | Model::AppleIIPlus | Model::AppleIIe => CpuKind::Nmos6502,
+            Model::AppleIIeEnhanced | Model::AppleIIc => CpuKind::Cmos65C02,
+        }
+    }
+
+    /* 64K of auxiliary RAM and the MMU/IOU soft switches. */
+    pub fn has_aux_memory(&self) -> bool {
+        !self.is_ii_or_plus()
+    }
+
+    /* Models with the language card on the motherboard in place of slot 0. */
+    pub fn has_builtin_language_card(&self) -> bool {
+        !self.is_ii_or_plus()
+    }
+
+    /* Only the II and II+ take 4K and 16K RAM chips in their three rows
+     * of sockets; the later machines always have 64K.
+     */
+    pub fn has_configurable_ram(&self) -> bool {
+        self.is_ii_or_plus()
+    }
+
+    /* Lowercase came with the //e; the II and II+ show $E0-$FF as symbols. */
+    pub fn has_lowercase(&self) -> bool {
+        !self.is_ii_or_plus()
+    }
+
+    fn is_ii_or_plus(&self) -> bool {
+        match *self {
+            Model::AppleII | Model::AppleIIPlus => true,
+            _ => false,
+        }
+    }
+
+    /* The //c has no slots. Its ports act as cards in fixed slots and
+     * all of $C100-$CFFF is its own rom.
+     */
+    pub fn has_slots(&self) -> bool {
+        *self != Model::AppleIIc
+    }
+}
@@ -1,280 +1,633 @@
-use mapper::{Mapper, APPLE_II_TEXT_WIDTH, APPLE_II_TEXT_HEIGHT};
+use char_rom::CharRom;
+use mapper::{Mapper, ScreenState, APPLE_II_TEXT_WIDTH};
+use ntsc::{self, DOTS_PER_ROW};
+use palette::{Palette, Rgb};
+use peripheral_card::PeripheralCard;
+use scanner::{self, Fetch, FetchKind};
+use video_output::{Frame, VideoOutput};
+use png;
+use recorder::Recorder;
 
-use std::path::Path;
-
-use sdl2::VideoSubsystem;
-use sdl2::render::{Renderer, Texture, TextureAccess};
-use sdl2::pixels::Color;
-use sdl2::rect::{Rect, Point};
-use sdl2::image::{self, LoadTexture, INIT_PNG};
-
-const FONT_PATH: &'static str = "resources/font.png";
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::mem;
 
 const APPLE_II_SCREEN_WIDTH: usize = 280;
 const APPLE_II_SCREEN_HEIGHT: usize = 192;
 
+/* The frame is drawn at double width so 80 column text and NTSC
+ * get a dot per pixel; everything else is doubled horizontally.
+ * Pixels are RGBA.
+ */
+pub const FRAME_WIDTH: usize = APPLE_II_SCREEN_WIDTH * 2;
+pub const FRAME_HEIGHT: usize = APPLE_II_SCREEN_HEIGHT;
+const FRAME_PITCH: usize = FRAME_WIDTH * 4;
+
+const BLACK: Rgb = (0x00, 0x00, 0x00);
+const WHITE: Rgb = (0xFF, 0xFF, 0xFF);
+
+/* How graphics modes get turned into pixels. Simple colors each dot by
+ * its neighbours; NTSC decodes the composite signal like a color monitor.
+ * Monochrome shows every dot as it is, in the color of the phosphor.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    Simple,
+    Ntsc,
+    Mono(Phosphor),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Phosphor {
+    Green,
+    Amber,
+    White,
+}
+
+impl Phosphor {
+    fn color(&self) -> Rgb {
+        match *self {
+            Phosphor::Green => (0x33, 0xFF, 0x33),
+            Phosphor::Amber => (0xFF, 0xB0, 0x00),
+            Phosphor::White => (0xFF, 0xFF, 0xFF),
+        }
+    }
+}
+
+impl RenderMode {
+    pub fn from_name(name: &str) -> Option<RenderMode> {
+        match name {
+            "simple" => Some(RenderMode::Simple),
+            "ntsc" => Some(RenderMode::Ntsc),
+            "green" => Some(RenderMode::Mono(Phosphor::Green)),
+            "amber" => Some(RenderMode::Mono(Phosphor::Amber)),
+            "white" => Some(RenderMode::Mono(Phosphor::White)),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RenderMode::Simple => "simple",
+            RenderMode::Ntsc => "ntsc",
+            RenderMode::Mono(Phosphor::Green) => "green",
+            RenderMode::Mono(Phosphor::Amber) => "amber",
+            RenderMode::Mono(Phosphor::White) => "white",
+        }
+    }
+
+    /* The mode after this one when cycling through them at runtime. */
+    pub fn next(&self) -> RenderMode {
+        match *self {
+            RenderMode::Simple => RenderMode::Ntsc,
+            RenderMode::Ntsc => RenderMode::Mono(Phosphor::Green),
+            RenderMode::Mono(Phosphor::Green) => RenderMode::Mono(Phosphor::Amber),
+            RenderMode::Mono(Phosphor::Amber) => RenderMode::Mono(Phosphor::White),
+            RenderMode::Mono(Phosphor::White) => RenderMode::Simple,
+        }
+    }
+
+    /* What lit dots and text are drawn in. */
+    fn foreground(&self) -> Rgb {
+        match *self {
+            RenderMode::Mono(phosphor) => phosphor.color(),
+            _ => WHITE,
+        }
+    }
+}
+
+/* How big a screenshot is: the Apple's own resolution, or the size
+ * of the window it is being shown in.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum ShotSize {
+    Native,
+    Window,
+}
+
+/* Output of a card with its own video, like an 80 column card. */
+struct CardVideo {
+    width: usize,
+    height: usize,
+    frame: Vec<bool>,
+    pixels: Vec<u8>,
+}
+
+/* Generates the Apple's picture from what the video circuitry fetches
+ * and hands each finished frame to an output to show.
+ */
 pub struct Monitor<'a> {
-    pub renderer: Renderer<'a>,
-    pub font: Texture,
+    output: Box<VideoOutput + 'a>,
+    frame: Vec<u8>,
+    /* rows changed since the last frame was shown */
+    dirty: Vec<bool>,
+    /* the last frame shown was a card's, not the Apple's */
+    showing_card: bool,
+    /* what each row was last drawn from, so unchanged rows are skipped */
+    drawn: Vec<Fetch>,
+    up_to_date: Vec<bool>,
+    chars: CharRom,
+    card_video: Option<CardVideo>,
+    mode: RenderMode,
+    palette: Palette,
+    /* scratch space for building a line a dot at a time */
+    dots: Vec<bool>,
+    /* which half of the flash cycle the rows were drawn in */
+    flash: bool,
+    /* the cycle the beam has been drawn up to */
+    beam: u64,
+    /* what the beam has fetched so far along the current line */
+    line: [Fetch; APPLE_II_TEXT_WIDTH],
+    recorder: Option<Recorder>,
 }
 
 impl<'a> Monitor<'a> {
-    pub fn new(sdl_video: VideoSubsystem) -> Monitor<'a> {
-        /* Perhaps have AppleII init SDL_Image? */
-        let _sdl_img_ctx = image::init(INIT_PNG).expect("Could not init SDL2 image.");
-
-        let mut window = sdl_video.window("APPLE ][",
-                    APPLE_II_SCREEN_WIDTH as u32,
-                    APPLE_II_SCREEN_HEIGHT as u32)
-            .position_centered()
-            .build()
-            .expect("Could not make window.");
-        window.set_minimum_size(APPLE_II_SCREEN_WIDTH as u32, APPLE_II_SCREEN_HEIGHT as u32)
-            .expect("Could not set min size.");
-
-        let mut renderer = window.renderer()
-            .accelerated()
-            .target_texture()
-            .build()
-            .expect("Could not make renderer");
-
-        let window_format = renderer.window()
-            .expect("Could not get window.")
-            .window_pixel_format();
-        let vbuf = renderer.create_texture(window_format,
-                            TextureAccess::Target,
-                            APPLE_II_SCREEN_WIDTH as u32,
-                            APPLE_II_SCREEN_HEIGHT as u32)
-            .expect("Could not create texture.");
-
-        let font = renderer.load_texture(Path::new(FONT_PATH))
-            .expect("Could not load font file.");
-
-        /* render_target() should be guarenteed to return Some(RenderTarget)
-         * since we specified TextureAccess::Target.
-         */
-        renderer.render_target()
-            .unwrap()
-            .set(vbuf)
-            .expect("Could not set render target");
-
+    pub fn new(output: Box<VideoOutput + 'a>,
+               chars: CharRom,
+               mode: RenderMode,
+               palette: Palette)
+               -> Monitor<'a> {
         Monitor {
-            renderer: renderer,
-            font: font,
+            output: output,
+            frame: vec![0; FRAME_PITCH * APPLE_II_SCREEN_HEIGHT],
+            dirty: vec![true; APPLE_II_SCREEN_HEIGHT],
+            showing_card: false,
+            drawn: vec![Fetch::new(); APPLE_II_TEXT_WIDTH * APPLE_II_SCREEN_HEIGHT],
+            up_to_date: vec![false; APPLE_II_SCREEN_HEIGHT],
+            chars: chars,
+            card_video: None,
+            mode: mode,
+            palette: palette,
+            dots: vec![false; DOTS_PER_ROW],
+            flash: false,
+            beam: 0,
+            line: [Fetch::new(); APPLE_II_TEXT_WIDTH],
+            recorder: None,
         }
     }
 
-    fn draw_text_row(&mut self, memory: &Mapper, scr_base: usize, y: usize, cycles: u64) {
-        static ROW_MAP: [usize; APPLE_II_TEXT_HEIGHT] =
-            [0x000, 0x080, 0x100, 0x180, 0x200, 0x280, 0x300, 0x380, 0x028, 0x0A8, 0x128, 0x1A8,
-             0x228, 0x2A8, 0x328, 0x3A8, 0x050, 0x0D0, 0x150, 0x1D0, 0x250, 0x2D0, 0x350, 0x3D0];
-
-        let base = scr_base + ROW_MAP[y];
-        for x in 0..APPLE_II_TEXT_WIDTH {
-            let mut character = memory.ram[base + x];
-            let char_type = character >> 6;
-            match char_type {
-                0 => character |= 0x40,
-                1 => {
-                    if cycles % 1000000 < 500000 {
-                        character |= 0x40;
-                    } else {
-                        character &= 0x3F;
-                    }
+    pub fn mode(&self) -> RenderMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+        self.invalidate();
+    }
+
+    pub fn cycle_mode(&mut self) {
+        let next = self.mode.next();
+        self.set_mode(next);
+    }
+
+    /* Makes every row get drawn again next time the beam passes it. */
+    fn invalidate(&mut self) {
+        for row in self.up_to_date.iter_mut() {
+            *row = false;
+        }
+    }
+
+    fn draw_card_video(&mut self, card: &PeripheralCard) {
+        let (width, height) = card.video_size().unwrap();
+
+        let reuse = match self.card_video {
+            Some(ref video) => video.width == width && video.height == height,
+            None => false,
+        };
+        if !reuse {
+            self.card_video = Some(CardVideo {
+                width: width,
+                height: height,
+                frame: vec![false; width * height],
+                pixels: vec![0; width * height * 4],
+            });
+        }
+
+        let foreground = self.mode.foreground();
+        let video = self.card_video.as_mut().unwrap();
+        card.draw_video(&mut video.frame);
+        mono_pixels(&video.frame, &mut video.pixels, foreground);
+    }
+
+    fn draw_text(&mut self, y: usize, line: &[Fetch]) {
+        let color = self.mode.foreground();
+        let glyph_row = y % 8;
+        let (chars, flash) = (&self.chars, self.flash);
+        let row = &mut self.frame[y * FRAME_PITCH..(y + 1) * FRAME_PITCH];
+        for (x, fetch) in line.iter().enumerate() {
+            let left = x * 14 * 4;
+            let dots = |character| chars.row(character, glyph_row, fetch.altcharset, flash);
+            match fetch.kind {
+                FetchKind::Text => draw_dots_row(dots(fetch.main), 2, color, &mut row[left..]),
+                /* 80 column text interleaves aux memory (even columns) with main */
+                FetchKind::Text80 => {
+                    draw_dots_row(dots(fetch.aux), 1, color, &mut row[left..]);
+                    draw_dots_row(dots(fetch.main), 1, color, &mut row[left + 7 * 4..]);
                 }
-                _ => character &= 0x3F,
+                _ => {}
             }
-            let font_y = (character & 0x7) as i32 * 8;
-            let font_x = ((character & 0x78) >> 3) as i32 * 7;
-            let src = Some(Rect::new(font_x, font_y, 7, 8));
-            let dst = Some(Rect::new(x as i32 * 7, y as i32 * 8, 7, 8));
-            self.renderer
-                .copy(&self.font, src, dst)
-                .expect("Could not copy texture.");
         }
     }
 
-    fn draw_low_res_row(&mut self, memory: &Mapper, scr_base: usize, y: usize) {
-        static ROW_MAP: [usize; APPLE_II_TEXT_HEIGHT] =
-            [0x000, 0x080, 0x100, 0x180, 0x200, 0x280, 0x300, 0x380, 0x028, 0x0A8, 0x128, 0x1A8,
-             0x228, 0x2A8, 0x328, 0x3A8, 0x050, 0x0D0, 0x150, 0x1D0, 0x250, 0x2D0, 0x350, 0x3D0];
-        static COLOR_MAP: [Color; 0x10] = [Color::RGB(0x00, 0x00, 0x00),
-                                           Color::RGB(0xD0, 0x00, 0x30),
-                                           Color::RGB(0x00, 0x00, 0x80),
-                                           Color::RGB(0xFF, 0x00, 0xFF),
-                                           Color::RGB(0x00, 0x80, 0x00),
-                                           Color::RGB(0x80, 0x80, 0x80),
-                                           Color::RGB(0x00, 0x00, 0xFF),
-                                           Color::RGB(0x60, 0xA0, 0xFF),
-                                           Color::RGB(0x80, 0x50, 0x00),
-                                           Color::RGB(0xFF, 0x80, 0x00),
-                                           Color::RGB(0xC0, 0xC0, 0xC0),
-                                           Color::RGB(0xFF, 0x90, 0x80),
-                                           Color::RGB(0x00, 0xFF, 0x00),
-                                           Color::RGB(0xFF, 0xFF, 0x00),
-                                           Color::RGB(0x40, 0xFF, 0x90),
-                                           Color::RGB(0xFF, 0xFF, 0xFF)];
-
-        let base = scr_base + ROW_MAP[y];
-        for x in 0..APPLE_II_TEXT_WIDTH {
-            let colors = memory.ram[base + x];
-            self.renderer.set_draw_color(COLOR_MAP[(colors & 0xF) as usize]);
-            self.renderer
-                .fill_rect(Rect::new(x as i32 * 7, y as i32 * 8, 7, 4))
-                .expect("Could not draw to screen.");
-            self.renderer.set_draw_color(COLOR_MAP[(colors >> 4) as usize]);
-            self.renderer
-                .fill_rect(Rect::new(x as i32 * 7, y as i32 * 8 + 4, 7, 4))
-                .expect("Could not draw to screen.");
+    fn draw_low_res(&mut self, y: usize, line: &[Fetch]) {
+        let row = &mut self.frame[y * FRAME_PITCH..(y + 1) * FRAME_PITCH];
+        for (x, fetch) in line.iter().enumerate() {
+            if fetch.kind == FetchKind::LowRes {
+                let color = self.palette.lores[lores_color(fetch.main, y) as usize];
+                fill(row, x * 14, 14, color);
+            }
         }
     }
 
-    fn draw_high_res_row(&mut self, memory: &Mapper, scr_base: usize, y: usize) {
-        static ROW_MAP: [usize; APPLE_II_SCREEN_HEIGHT] =
-            [0x0, 0x400, 0x800, 0xc00, 0x1000, 0x1400, 0x1800, 0x1c00, 0x80, 0x480, 0x880, 0xc80,
-             0x1080, 0x1480, 0x1880, 0x1c80, 0x100, 0x500, 0x900, 0xd00, 0x1100, 0x1500, 0x1900,
-             0x1d00, 0x180, 0x580, 0x980, 0xd80, 0x1180, 0x1580, 0x1980, 0x1d80, 0x200, 0x600,
-             0xa00, 0xe00, 0x1200, 0x1600, 0x1a00, 0x1e00, 0x280, 0x680, 0xa80, 0xe80, 0x1280,
-             0x1680, 0x1a80, 0x1e80, 0x300, 0x700, 0xb00, 0xf00, 0x1300, 0x1700, 0x1b00, 0x1f00,
-             0x380, 0x780, 0xb80, 0xf80, 0x1380, 0x1780, 0x1b80, 0x1f80, 0x28, 0x428, 0x828,
-             0xc28, 0x1028, 0x1428, 0x1828, 0x1c28, 0xa8, 0x4a8, 0x8a8, 0xca8, 0x10a8, 0x14a8,
-             0x18a8, 0x1ca8, 0x128, 0x528, 0x928, 0xd28, 0x1128, 0x1528, 0x1928, 0x1d28, 0x1a8,
-             0x5a8, 0x9a8, 0xda8, 0x11a8, 0x15a8, 0x19a8, 0x1da8, 0x228, 0x628, 0xa28, 0xe28,
-             0x1228, 0x1628, 0x1a28, 0x1e28, 0x2a8, 0x6a8, 0xaa8, 0xea8, 0x12a8, 0x16a8, 0x1aa8,
-             0x1ea8, 0x328, 0x728, 0xb28, 0xf28, 0x1328, 0x1728, 0x1b28, 0x1f28, 0x3a8, 0x7a8,
-             0xba8, 0xfa8, 0x13a8, 0x17a8, 0x1ba8, 0x1fa8, 0x50, 0x450, 0x850, 0xc50, 0x1050,
-             0x1450, 0x1850, 0x1c50, 0xd0, 0x4d0, 0x8d0, 0xcd0, 0x10d0, 0x14d0, 0x18d0, 0x1cd0,
-             0x150, 0x550, 0x950, 0xd50, 0x1150, 0x1550, 0x1950, 0x1d50, 0x1d0, 0x5d0, 0x9d0,
-             0xdd0, 0x11d0, 0x15d0, 0x19d0, 0x1dd0, 0x250, 0x650, 0xa50, 0xe50, 0x1250, 0x1650,
-             0x1a50, 0x1e50, 0x2d0, 0x6d0, 0xad0, 0xed0, 0x12d0, 0x16d0, 0x1ad0, 0x1ed0, 0x350,
-             0x750, 0xb50, 0xf50, 0x1350, 0x1750, 0x1b50, 0x1f50, 0x3d0, 0x7d0, 0xbd0, 0xfd0,
-             0x13d0, 0x17d0, 0x1bd0, 0x1fd0];
-
-        let base = scr_base + ROW_MAP[y];
+    fn draw_high_res(&mut self, y: usize, line: &[Fetch]) {
+        let palette = self.palette;
+        let row = &mut self.frame[y * FRAME_PITCH..(y + 1) * FRAME_PITCH];
+        let byte_at = |col: usize| {
+            if line[col].kind == FetchKind::HighRes { line[col].main } else { 0 }
+        };
         let mut prev;
         let mut curr = 0;
         /* prevents the very first bit from not being seen */
-        let mut next = memory.ram[base] & 0x1;
+        let mut next = byte_at(0) & 0x1;
         let mut x = 0;
         for byte in 0..APPLE_II_SCREEN_WIDTH / 7 {
-            let data = memory.ram[base + byte];
+            let data = byte_at(byte);
             let colorset = data & 0x80;
             for bit in 0..7 {
                 prev = curr;
                 curr = next;
                 next = data & (1 << bit);
 
-                if curr != 0 {
+                let color = if curr != 0 {
                     if (prev != 0) || (next != 0) {
-                        /* white */
-                        self.renderer.set_draw_color(Color::RGB(0xFF, 0xFF, 0xFF));
+                        WHITE
                     } else if colorset != 0 {
-                        if (x & 1) != 0 {
-                            /* blue */
-                            self.renderer.set_draw_color(Color::RGB(0x00, 0x80, 0xFF));
-                        } else {
-                            /* red */
-                            self.renderer.set_draw_color(Color::RGB(0xF0, 0x50, 0x00));
-                        }
+                        if (x & 1) != 0 { palette.blue } else { palette.orange }
                     } else {
-                        if (x & 1) != 0 {
-                            /* violet */
-                            self.renderer.set_draw_color(Color::RGB(0xA0, 0x00, 0xFF));
-                        } else {
-                            /* green */
-                            self.renderer.set_draw_color(Color::RGB(0x20, 0xC0, 0x00));
-                        }
+                        if (x & 1) != 0 { palette.violet } else { palette.green }
                     }
                 } else if (prev != 0) && (next != 0) {
                     if colorset != 0 {
-                        if (x & 1) != 0 {
-                            /* red */
-                            self.renderer.set_draw_color(Color::RGB(0xF0, 0x50, 0x00));
-                        } else {
-                            /* blue */
-                            self.renderer.set_draw_color(Color::RGB(0x00, 0x80, 0xFF));
-                        }
+                        if (x & 1) != 0 { palette.orange } else { palette.blue }
                     } else {
-                        if (x & 1) != 0 {
-                            /* green */
-                            self.renderer.set_draw_color(Color::RGB(0x20, 0xC0, 0x00));
-                        } else {
-                            /* violet */
-                            self.renderer.set_draw_color(Color::RGB(0xA0, 0x00, 0xFF));
-                        }
+                        if (x & 1) != 0 { palette.green } else { palette.violet }
                     }
                 } else {
-                    /* black */
-                    self.renderer.set_draw_color(Color::RGB(0x00, 0x00, 0x00));
+                    BLACK
+                };
+                if line[byte].kind == FetchKind::HighRes {
+                    fill(row, x * 2, 2, color);
                 }
-                self.renderer
-                    .draw_point(Point::new(x, y as i32))
-                    .expect("Could not render point.");
                 x += 1;
             }
         }
     }
 
-    pub fn update_window(&mut self, memory: &Mapper, cycles: u64) {
-        if memory.screen.graphics {
-            if memory.screen.low_res {
-                if memory.screen.primary {
-                    for y in 0..APPLE_II_TEXT_HEIGHT {
-                        self.draw_low_res_row(memory, 0x400, y);
-                    }
-                } else {
-                    for y in 0..APPLE_II_TEXT_HEIGHT {
-                        self.draw_low_res_row(memory, 0x800, y);
-                    }
-                }
-            } else {
-                if memory.screen.primary {
-                    for y in 0..APPLE_II_SCREEN_HEIGHT {
-                        self.draw_high_res_row(memory, 0x2000, y);
-                    }
-                } else {
-                    for y in 0..APPLE_II_SCREEN_HEIGHT {
-                        self.draw_high_res_row(memory, 0x4000, y);
+    /* Builds the graphics part of a line from its dots, decoded through
+     * the NTSC model or shown as they are on a monochrome monitor.
+     */
+    fn draw_dots(&mut self, y: usize, line: &[Fetch]) {
+        let mut bytes = [0u8; APPLE_II_TEXT_WIDTH];
+        let mut colors = [0u8; APPLE_II_TEXT_WIDTH];
+        for (x, fetch) in line.iter().enumerate() {
+            match fetch.kind {
+                FetchKind::HighRes => bytes[x] = fetch.main,
+                FetchKind::LowRes => colors[x] = lores_color(fetch.main, y),
+                _ => {}
+            }
+        }
+
+        let mut lores = [false; DOTS_PER_ROW];
+        ntsc::hires_dots(&bytes, &mut self.dots);
+        ntsc::lores_dots(&colors, &mut lores);
+        for (x, fetch) in line.iter().enumerate() {
+            let cols = x * 14..(x + 1) * 14;
+            match fetch.kind {
+                FetchKind::HighRes => {}
+                FetchKind::LowRes => self.dots[cols.clone()].copy_from_slice(&lores[cols]),
+                /* text gets drawn over the top */
+                _ => {
+                    for dot in self.dots[cols].iter_mut() {
+                        *dot = false;
                     }
                 }
             }
+        }
+
+        let row = &mut self.frame[y * FRAME_PITCH..(y + 1) * FRAME_PITCH];
+        match self.mode {
+            RenderMode::Mono(phosphor) => mono_pixels(&self.dots, row, phosphor.color()),
+            _ => ntsc::decode(&self.dots, row),
+        }
+    }
+
+    fn draw_line(&mut self, y: usize) {
+        let flash = scanner::flash_inverse(self.beam);
+        if flash != self.flash {
+            /* flashing characters change without their bytes changing */
+            self.flash = flash;
+            self.invalidate();
+        }
+
+        let line = self.line;
+        let cols = y * APPLE_II_TEXT_WIDTH..(y + 1) * APPLE_II_TEXT_WIDTH;
+        if self.up_to_date[y] && self.drawn[cols.clone()] == line[..] {
+            return;
+        }
+        self.drawn[cols].copy_from_slice(&line);
+        self.up_to_date[y] = true;
+        self.dirty[y] = true;
+        self.render_line(y, &line);
+    }
+
+    fn render_line(&mut self, y: usize, line: &[Fetch]) {
+        if self.mode == RenderMode::Simple {
+            self.draw_low_res(y, line);
+            self.draw_high_res(y, line);
+        } else {
+            self.draw_dots(y, line);
+        }
+        self.draw_text(y, line);
+    }
+
+    /* The frame on screen drawn over again in another mode, from what
+     * each of its rows was drawn from.
+     */
+    fn redraw(&mut self, mode: RenderMode) -> Vec<u8> {
+        let shown_mode = self.mode;
+        let shown = mem::replace(&mut self.frame, vec![0; FRAME_PITCH * FRAME_HEIGHT]);
+        self.mode = mode;
+        for y in 0..FRAME_HEIGHT {
+            let cols = y * APPLE_II_TEXT_WIDTH..(y + 1) * APPLE_II_TEXT_WIDTH;
+            let mut line = [Fetch::new(); APPLE_II_TEXT_WIDTH];
+            line.copy_from_slice(&self.drawn[cols]);
+            self.render_line(y, &line);
+        }
+        self.mode = shown_mode;
+        mem::replace(&mut self.frame, shown)
+    }
+
+    /* Saves what is on screen as a PNG. At native size that is the
+     * Apple's 280x192, or 560x192 when 80 column text or the color and
+     * monochrome modes use every dot; at window size it is scaled up to
+     * match the window. Unless `as_shown` is set the picture is drawn in
+     * plain palette colors rather than the current mode.
+     */
+    pub fn screenshot(&mut self, path: &str, size: ShotSize, as_shown: bool) -> io::Result<()> {
+        let card = match self.card_video {
+            Some(ref video) if self.showing_card => {
+                Some((video.width, video.height, video.pixels.clone()))
+            }
+            _ => None,
+        };
+        let (mut width, mut height, mut pixels) = match card {
+            Some(card) => card,
+            None if as_shown || self.mode == RenderMode::Simple => {
+                (FRAME_WIDTH, FRAME_HEIGHT, self.frame.clone())
+            }
+            None => (FRAME_WIDTH, FRAME_HEIGHT, self.redraw(RenderMode::Simple)),
+        };
+
+        let every_dot = self.showing_card || (as_shown && self.mode != RenderMode::Simple) ||
+                        self.drawn.iter().any(|fetch| fetch.kind == FetchKind::Text80);
+        match size {
+            ShotSize::Native if !every_dot => {
+                pixels = scale(&pixels, width, height, width / 2, height);
+                width /= 2;
+            }
+            ShotSize::Native => {}
+            ShotSize::Window => {
+                /* the window opens at twice the frame's height */
+                let (to_width, to_height) = self.output
+                    .window_size()
+                    .unwrap_or((FRAME_WIDTH, FRAME_HEIGHT * 2));
+                pixels = scale(&pixels, width, height, to_width, to_height);
+                width = to_width;
+                height = to_height;
+            }
+        }
+
+        let mut file = BufWriter::new(try!(File::create(path)));
+        png::write_rgba(&mut file, width, height, &pixels)
+    }
+
+    /* Records every frame from the next one on, until stop_recording(). */
+    pub fn record(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /* Hands back the recorder, for its files to be finished. */
+    pub fn stop_recording(&mut self) -> Option<Recorder> {
+        self.recorder.take()
+    }
+
+    fn recording_failed(&mut self, err: io::Error) {
+        error!("Recording stopped: {}", err);
+        self.recorder = None;
+    }
+
+    /* Runs the beam up to the given cycle, latching what it fetches
+     * as it goes so switches flipped partway down the screen (or across
+     * a line) show where they happened. Each line is drawn once the beam
+     * reaches its end, and the frame is shown when it reaches the bottom.
+     */
+    pub fn scan_to(&mut self, memory: &Mapper, cycles: u64) {
+        let screen = memory.screen;
+        self.scan(memory, &screen, cycles);
+
+        let failed = match self.recorder {
+            Some(ref mut recorder) => recorder.speaker(cycles, memory.speaker).err(),
+            None => None,
+        };
+        if let Some(err) = failed {
+            self.recording_failed(err);
+        }
+    }
 
-            if !memory.screen.all {
-                for y in APPLE_II_TEXT_HEIGHT - 4..APPLE_II_TEXT_HEIGHT {
-                    self.draw_text_row(memory, 0x400, y, cycles);
+    /* Runs the beam up to the cycle a display switch flipped on, with
+     * the switches as they were before, so the change shows from the
+     * column where the access happened rather than where the
+     * instruction started.
+     */
+    pub fn scan_before_switch(&mut self, memory: &Mapper, screen: &ScreenState, cycles: u64) {
+        self.scan(memory, screen, cycles);
+    }
+
+    fn scan(&mut self, memory: &Mapper, screen: &ScreenState, cycles: u64) {
+        while self.beam < cycles {
+            if let Some((y, x)) = scanner::position(self.beam) {
+                self.line[x] = scanner::fetch(self.beam, memory, screen);
+                if x == APPLE_II_TEXT_WIDTH - 1 {
+                    self.draw_line(y);
+                    if y == APPLE_II_SCREEN_HEIGHT - 1 {
+                        self.show(memory, true);
+                    }
                 }
             }
+            self.beam += 1;
+        }
+    }
+
+    /* Hands the last frame the beam drew to the output again, as when
+     * the machine is paused.
+     */
+    pub fn present(&mut self, memory: &Mapper) {
+        self.show(memory, false);
+    }
+
+    /* Hands the last frame the beam drew to the output, and to the
+     * recorder if it is a new one. The soft video switch shows an 80
+     * column card's output instead of the Apple's while in text mode
+     * with AN0 on.
+     */
+    fn show(&mut self, memory: &Mapper, new_frame: bool) {
+        let card = if !memory.screen.graphics && memory.annunciators[0] {
+            memory.video_card()
         } else {
-            if memory.screen.primary {
-                for y in 0..APPLE_II_TEXT_HEIGHT {
-                    self.draw_text_row(memory, 0x400, y, cycles);
+            None
+        };
+
+        if let Some(card) = card {
+            self.draw_card_video(card);
+            self.showing_card = true;
+        } else if self.showing_card {
+            /* the output last had the card's picture, so it needs all of ours */
+            for dirty in self.dirty.iter_mut() {
+                *dirty = true;
+            }
+            self.showing_card = false;
+        }
+
+        let recorded = {
+            let frame = match self.card_video {
+                Some(ref video) if self.showing_card => {
+                    Frame {
+                        width: video.width,
+                        height: video.height,
+                        pixels: &video.pixels,
+                        changed: Some((0, video.height - 1)),
+                    }
                 }
-            } else {
-                for y in 0..APPLE_II_TEXT_HEIGHT {
-                    self.draw_text_row(memory, 0x800, y, cycles);
+                _ => {
+                    let first = self.dirty.iter().position(|dirty| *dirty);
+                    let last = self.dirty.iter().rposition(|dirty| *dirty);
+                    Frame {
+                        width: FRAME_WIDTH,
+                        height: FRAME_HEIGHT,
+                        pixels: &self.frame,
+                        changed: first.and_then(|first| last.map(|last| (first, last))),
+                    }
                 }
+            };
+            self.output.show(&frame);
+            match self.recorder {
+                Some(ref mut recorder) if new_frame => recorder.frame(&frame, self.beam),
+                _ => Ok(()),
             }
+        };
+        if let Err(err) = recorded {
+            self.recording_failed(err);
         }
 
-        self.renderer.present();
-        let vbuf = self.renderer
-            .render_target()
-            .unwrap()
-            .reset()
-            .expect("Could not reset render target.")
-            .unwrap();
-        let (w, h) = self.renderer.window().unwrap().size();
-        self.renderer
-            .set_logical_size(w, h)
-            .expect("Could not set logical size");
-        self.renderer
-            .copy(&vbuf, None, Some(Rect::new(0, 0, w, h)))
-            .expect("Could not copy texture.");
-        self.renderer.present();
-        self.renderer
-            .render_target()
-            .unwrap()
-            .set(vbuf)
-            .expect("Could not set render target.");
+        if !self.showing_card {
+            for dirty in self.dirty.iter_mut() {
+                *dirty = false;
+            }
+        }
+    }
+}
+
+/* Resizes RGBA pixels, picking the nearest one for each. */
+pub fn scale(pixels: &[u8],
+             width: usize,
+             height: usize,
+             to_width: usize,
+             to_height: usize)
+             -> Vec<u8> {
+    let mut scaled = Vec::with_capacity(to_width * to_height * 4);
+    for y in 0..to_height {
+        let row = y * height / to_height;
+        for x in 0..to_width {
+            let offset = (row * width + x * width / to_width) * 4;
+            scaled.extend_from_slice(&pixels[offset..offset + 4]);
+        }
+    }
+    scaled
+}
+
+/* Draws a row of seven dots, bit 0 leftmost, each `dot_width` pixels wide. */
+fn draw_dots_row(dots: u8, dot_width: usize, color: Rgb, pixels: &mut [u8]) {
+    for dot in 0..7 {
+        let lit = if dots & (1 << dot) != 0 { color } else { BLACK };
+        fill(pixels, dot * dot_width, dot_width, lit);
+    }
+}
+
+/* Each lores block is four lines of the low nibble then four of the high. */
+fn lores_color(colors: u8, y: usize) -> u8 {
+    if y % 8 < 4 { colors & 0xF } else { colors >> 4 }
+}
+
+/* Sets `width` RGBA pixels starting at pixel `x`. */
+fn fill(pixels: &mut [u8], x: usize, width: usize, (r, g, b): Rgb) {
+    for pixel in pixels[x * 4..(x + width) * 4].chunks_mut(4) {
+        pixel[0] = r;
+        pixel[1] = g;
+        pixel[2] = b;
+        pixel[3] = 0xFF;
+    }
+}
+
+/* Lights each dot in the foreground color, everything else black. */
+fn mono_pixels(dots: &[bool], pixels: &mut [u8], color: Rgb) {
+    for (x, dot) in dots.iter().enumerate() {
+        fill(pixels, x, 1, if *dot { color } else { BLACK });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::RAM_SIZE;
+    use model::Model;
+    use r6502::memory::Memory;
+    use ram_init::RamInit;
+    use scanner::{CYCLES_PER_LINE, HBLANK_CYCLES};
+    use video_output::Headless;
+
+    #[test]
+    fn switches_show_from_the_column_they_flip_on() {
+        let mut memory = Mapper::new(Model::AppleIIPlus, vec![0; 0x3000], RAM_SIZE, RamInit::Zero);
+        let mut monitor = Monitor::new(Box::new(Headless::new()),
+                                       CharRom::builtin(Model::AppleIIPlus),
+                                       RenderMode::Simple,
+                                       Palette::preset("emulator").unwrap());
+
+        /* LDA $C050 on line 10, reading the switch on column 20's cycle */
+        let column = 10 * CYCLES_PER_LINE + HBLANK_CYCLES + 20;
+        let start = column - 3;
+        monitor.scan_to(&memory, start);
+        memory.start_instruction(start);
+        for addr in 0..3 {
+            memory.read(addr);
+        }
+        memory.read(0xC050);
+
+        let changes = memory.take_screen_changes();
+        assert_eq!(changes.len(), 1);
+        for (cycle, screen) in changes {
+            assert_eq!(cycle, column);
+            monitor.scan_before_switch(&memory, &screen, cycle);
+        }
+        monitor.scan_to(&memory, start + 4);
+
+        for x in 0..20 {
+            assert!(monitor.line[x].kind == FetchKind::Text, "column {}", x);
+        }
+        assert!(monitor.line[20].kind == FetchKind::LowRes);
     }
 }
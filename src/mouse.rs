@@ -0,0 +1,151 @@
+use scanner;
+
+/* Cycles between quadrature edges when playing back host motion */
+const CYCLES_PER_EDGE: u64 = 256;
+/* Motion past this is dropped rather than played back late */
+const MAX_PENDING: i32 = 64;
+
+/* One axis of the //c's mouse port.
+ *
+ * The mouse sends two quadrature signals per axis. Edges on X0/Y0 can
+ * interrupt, and at the interrupt the firmware reads X1/Y1 to tell
+ * which way the mouse went: high for right/down, low for left/up.
+ */
+struct Axis {
+    pending: i32,
+    line0: bool,
+    line1: bool,
+    /* interrupt on the rising edge of line 0, else the falling one */
+    rising: bool,
+    int: bool,
+}
+
+impl Axis {
+    fn new() -> Axis {
+        Axis {
+            pending: 0,
+            line0: false,
+            line1: false,
+            rising: true,
+            int: false,
+        }
+    }
+
+    fn step(&mut self) {
+        if self.pending == 0 {
+            return;
+        }
+
+        self.line0 = !self.line0;
+        if self.line0 == self.rising {
+            self.line1 = self.pending > 0;
+            self.int = true;
+            self.pending -= self.pending.signum();
+        }
+    }
+}
+
+/* The //c's built-in mouse port and the interrupts the IOU raises for it. */
+pub struct Mouse {
+    pub button: bool,
+    x: Axis,
+    y: Axis,
+    pub xy_int_enabled: bool,
+    pub vbl_int_enabled: bool,
+    pub vbl_int: bool,
+    in_vbl: bool,
+    timer: u64,
+}
+
+impl Mouse {
+    pub fn new() -> Mouse {
+        Mouse {
+            button: false,
+            x: Axis::new(),
+            y: Axis::new(),
+            xy_int_enabled: false,
+            vbl_int_enabled: false,
+            vbl_int: false,
+            in_vbl: false,
+            timer: 0,
+        }
+    }
+
+    pub fn move_by(&mut self, dx: i32, dy: i32) {
+        self.x.pending = clamp(self.x.pending + dx);
+        self.y.pending = clamp(self.y.pending + dy);
+    }
+
+    pub fn tick(&mut self, cycles: u64, now: u64) {
+        self.timer += cycles;
+        while self.timer >= CYCLES_PER_EDGE {
+            self.timer -= CYCLES_PER_EDGE;
+            self.x.step();
+            self.y.step();
+        }
+
+        let in_vbl = scanner::in_vblank(now);
+        if in_vbl && !self.in_vbl && self.vbl_int_enabled {
+            self.vbl_int = true;
+        }
+        self.in_vbl = in_vbl;
+    }
+
+    pub fn irq(&self) -> bool {
+        (self.xy_int_enabled && (self.x.int || self.y.int)) || self.vbl_int
+    }
+
+    pub fn reset(&mut self) {
+        self.xy_int_enabled = false;
+        self.vbl_int_enabled = false;
+        self.clear_xy_int();
+        self.vbl_int = false;
+    }
+
+    pub fn clear_xy_int(&mut self) {
+        self.x.int = false;
+        self.y.int = false;
+    }
+
+    pub fn set_x_edge(&mut self, rising: bool) {
+        self.x.rising = rising;
+    }
+
+    pub fn set_y_edge(&mut self, rising: bool) {
+        self.y.rising = rising;
+    }
+
+    pub fn x_rising(&self) -> bool {
+        self.x.rising
+    }
+
+    pub fn y_rising(&self) -> bool {
+        self.y.rising
+    }
+
+    pub fn x_int(&self) -> bool {
+        self.x.int
+    }
+
+    pub fn y_int(&self) -> bool {
+        self.y.int
+    }
+
+    pub fn x1(&self) -> bool {
+        self.x.line1
+    }
+
+    pub fn y1(&self) -> bool {
+        self.y.line1
+    }
+}
+
+fn clamp(pending: i32) -> i32 {
+    if pending > MAX_PENDING {
+        MAX_PENDING
+    } else if pending < -MAX_PENDING {
+        -MAX_PENDING
+    } else {
+        pending
+    }
+}
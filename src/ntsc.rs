@@ -0,0 +1,107 @@
+use std::f32::consts::PI;
+
+/* Composite video as the Apple generates it and a color monitor decodes it.
+ *
+ * Each scanline is 560 dots at 14.318MHz, four dots to a cycle of the
+ * color subcarrier. Hires bytes send each of their seven bits as two
+ * dots, starting one dot late when bit 7 is set; the late byte's first
+ * dot repeats whatever was being sent before it. Lores sends the four
+ * bits of a color over and over, lined up with the subcarrier.
+ *
+ * Decoding low-passes the dots for luma and demodulates them against
+ * the subcarrier over a couple of cycles for chroma, which is where
+ * the fringes and color bleed come from.
+ */
+
+pub const DOTS_PER_ROW: usize = 560;
+
+/* Luma is averaged over one subcarrier cycle, chroma over two. */
+const LUMA_WINDOW: usize = 4;
+const CHROMA_WINDOW: usize = 8;
+/* Lines lores color 1 up with magenta. */
+const HUE_OFFSET: f32 = 62.0 * PI / 180.0;
+const SATURATION: f32 = 1.0;
+
+/* The dots of a hires line, given its 40 bytes. */
+pub fn hires_dots(bytes: &[u8], dots: &mut [bool]) {
+    let mut x = 0;
+    let mut last = false;
+    for byte in bytes {
+        let delayed = byte & 0x80 != 0;
+        if delayed {
+            dots[x] = last;
+            x += 1;
+        }
+        for dot in 0..14 {
+            /* a delayed byte loses its final half dot */
+            if delayed && dot == 13 {
+                break;
+            }
+            last = byte & (1 << (dot / 2)) != 0;
+            dots[x] = last;
+            x += 1;
+        }
+    }
+}
+
+/* The dots of a lores line, given the 40 color nibbles for it. */
+pub fn lores_dots(colors: &[u8], dots: &mut [bool]) {
+    for (col, color) in colors.iter().enumerate() {
+        for dot in col * 14..(col + 1) * 14 {
+            dots[dot] = color & (1 << (dot % 4)) != 0;
+        }
+    }
+}
+
+/* Decodes a line of dots into RGBA pixels, one per dot. */
+pub fn decode(dots: &[bool], pixels: &mut [u8]) {
+    let phase_cos: Vec<f32> = (0..4).map(|n| (n as f32 * PI / 2.0 + HUE_OFFSET).cos()).collect();
+    let phase_sin: Vec<f32> = (0..4).map(|n| (n as f32 * PI / 2.0 + HUE_OFFSET).sin()).collect();
+    let level = |x: isize| -> f32 {
+        if x >= 0 && (x as usize) < dots.len() && dots[x as usize] {
+            1.0
+        } else {
+            0.0
+        }
+    };
+
+    for x in 0..dots.len() {
+        let center = x as isize;
+
+        let mut y = 0.0;
+        for k in 0..LUMA_WINDOW as isize {
+            y += level(center - LUMA_WINDOW as isize / 2 + k);
+        }
+        y /= LUMA_WINDOW as f32;
+
+        let mut i = 0.0;
+        let mut q = 0.0;
+        for k in 0..CHROMA_WINDOW as isize {
+            let pos = center - CHROMA_WINDOW as isize / 2 + k;
+            let s = level(pos);
+            let phase = (pos & 0x3) as usize;
+            i += s * phase_cos[phase];
+            q += s * phase_sin[phase];
+        }
+        i *= 2.0 * SATURATION / CHROMA_WINDOW as f32;
+        q *= 2.0 * SATURATION / CHROMA_WINDOW as f32;
+
+        let r = y + 0.956 * i + 0.621 * q;
+        let g = y - 0.272 * i - 0.647 * q;
+        let b = y - 1.106 * i + 1.703 * q;
+        pixels[x * 4] = to_byte(r);
+        pixels[x * 4 + 1] = to_byte(g);
+        pixels[x * 4 + 2] = to_byte(b);
+        pixels[x * 4 + 3] = 0xFF;
+    }
+}
+
+fn to_byte(val: f32) -> u8 {
+    if val <= 0.0 {
+        0
+    } else if val >= 1.0 {
+        0xFF
+    } else {
+        (val * 255.0) as u8
+    }
+}
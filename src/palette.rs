@@ -0,0 +1,192 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+
+use config::ConfigError;
+
+/* Colors the simple renderer draws lores and hires with.
+ *
+ * A palette file lists colors as RRGGBB, lores ones by number and the
+ * hires ones by name. Anything left out comes from the base preset:
+ *
+ *     # lines starting with '#' are comments
+ *     base = ntsc
+ *     1 = 902040
+ *     orange = F06010
+ *
+ * On the real machine the hires colors are lores 3, 12, 6 and 9, which
+ * is how the measured palettes have them.
+ */
+
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Clone, Copy)]
+pub struct Palette {
+    pub lores: [Rgb; 16],
+    pub violet: Rgb,
+    pub green: Rgb,
+    pub blue: Rgb,
+    pub orange: Rgb,
+}
+
+pub const DEFAULT_PALETTE: &'static str = "emulator";
+
+/* What this emulator has always drawn. */
+static EMULATOR: Palette = Palette {
+    lores: [(0x00, 0x00, 0x00),
+            (0xD0, 0x00, 0x30),
+            (0x00, 0x00, 0x80),
+            (0xFF, 0x00, 0xFF),
+            (0x00, 0x80, 0x00),
+            (0x80, 0x80, 0x80),
+            (0x00, 0x00, 0xFF),
+            (0x60, 0xA0, 0xFF),
+            (0x80, 0x50, 0x00),
+            (0xFF, 0x80, 0x00),
+            (0xC0, 0xC0, 0xC0),
+            (0xFF, 0x90, 0x80),
+            (0x00, 0xFF, 0x00),
+            (0xFF, 0xFF, 0x00),
+            (0x40, 0xFF, 0x90),
+            (0xFF, 0xFF, 0xFF)],
+    violet: (0xA0, 0x00, 0xFF),
+    green: (0x20, 0xC0, 0x00),
+    blue: (0x00, 0x80, 0xFF),
+    orange: (0xF0, 0x50, 0x00),
+};
+
+/* Worked out from the composite signal, as a color monitor shows it. */
+static NTSC: Palette = Palette {
+    lores: [(0x00, 0x00, 0x00),
+            (0x72, 0x26, 0x40),
+            (0x40, 0x33, 0x7F),
+            (0xE4, 0x34, 0xFE),
+            (0x0E, 0x59, 0x40),
+            (0x80, 0x80, 0x80),
+            (0x1B, 0x9A, 0xFE),
+            (0xBF, 0xB3, 0xFF),
+            (0x40, 0x4C, 0x00),
+            (0xE4, 0x65, 0x01),
+            (0x80, 0x80, 0x80),
+            (0xF1, 0xA6, 0xBF),
+            (0x1B, 0xCB, 0x01),
+            (0xBF, 0xCC, 0x80),
+            (0x8D, 0xD9, 0xBF),
+            (0xFF, 0xFF, 0xFF)],
+    violet: (0xE4, 0x34, 0xFE),
+    green: (0x1B, 0xCB, 0x01),
+    blue: (0x1B, 0x9A, 0xFE),
+    orange: (0xE4, 0x65, 0x01),
+};
+
+/* The IIgs RGB monitor's fixed colors. */
+static IIGS: Palette = Palette {
+    lores: [(0x00, 0x00, 0x00),
+            (0xDD, 0x00, 0x33),
+            (0x00, 0x00, 0x99),
+            (0xDD, 0x22, 0xDD),
+            (0x00, 0x77, 0x22),
+            (0x55, 0x55, 0x55),
+            (0x22, 0x22, 0xFF),
+            (0x66, 0xAA, 0xFF),
+            (0x88, 0x55, 0x00),
+            (0xFF, 0x66, 0x00),
+            (0xAA, 0xAA, 0xAA),
+            (0xFF, 0x99, 0x88),
+            (0x11, 0xDD, 0x00),
+            (0xFF, 0xFF, 0x00),
+            (0x44, 0xFF, 0x99),
+            (0xFF, 0xFF, 0xFF)],
+    violet: (0xDD, 0x22, 0xDD),
+    green: (0x11, 0xDD, 0x00),
+    blue: (0x22, 0x22, 0xFF),
+    orange: (0xFF, 0x66, 0x00),
+};
+
+impl Palette {
+    pub fn preset(name: &str) -> Option<Palette> {
+        match name {
+            "emulator" => Some(EMULATOR),
+            "ntsc" => Some(NTSC),
+            "iigs" => Some(IIGS),
+            _ => None,
+        }
+    }
+
+    /* A preset name, or else the path of a palette file. */
+    pub fn from_spec(spec: &str) -> Result<Palette, ConfigError> {
+        match Palette::preset(spec) {
+            Some(palette) => Ok(palette),
+            None => Palette::from_file(spec),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Palette, ConfigError> {
+        let file = try!(fs::File::open(path).map_err(|err| ConfigError::Io(path.to_string(), err)));
+        let mut palette = EMULATOR;
+        let mut seen = Vec::new();
+
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = try!(line.map_err(|err| ConfigError::Io(path.to_string(), err)));
+            try!(palette.parse_line(&line, &mut seen)
+                .map_err(|err| ConfigError::Line(path.to_string(), idx + 1, Box::new(err))));
+        }
+
+        Ok(palette)
+    }
+
+    fn parse_line(&mut self, line: &str, seen: &mut Vec<String>) -> Result<(), ConfigError> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let mut kv = line.splitn(2, '=');
+        let key = kv.next().unwrap().trim();
+        let val = match kv.next() {
+            Some(val) => val.trim(),
+            None => return Err(ConfigError::Syntax(format!("expected key = value, found \"{}\"", line))),
+        };
+
+        if seen.iter().any(|prev| prev == key) {
+            return Err(ConfigError::Syntax(format!("\"{}\" given twice", key)));
+        }
+        if key == "base" && !seen.is_empty() {
+            return Err(ConfigError::Syntax("base must come before any colors".to_string()));
+        }
+        seen.push(key.to_string());
+
+        if key == "base" {
+            *self = match Palette::preset(val) {
+                Some(palette) => palette,
+                None => return Err(ConfigError::Syntax(format!("unknown palette \"{}\"", val))),
+            };
+            return Ok(());
+        }
+
+        let color = try!(parse_rgb(val));
+        if let Ok(index) = key.parse::<usize>() {
+            if index >= self.lores.len() {
+                return Err(ConfigError::Syntax(format!("lores colors are 0 to 15, found {}", index)));
+            }
+            self.lores[index] = color;
+            return Ok(());
+        }
+        match key {
+            "violet" => self.violet = color,
+            "green" => self.green = color,
+            "blue" => self.blue = color,
+            "orange" => self.orange = color,
+            _ => return Err(ConfigError::Syntax(format!("unknown color \"{}\"", key))),
+        }
+        Ok(())
+    }
+}
+
+/* RRGGBB, with or without a leading '#' */
+fn parse_rgb(val: &str) -> Result<Rgb, ConfigError> {
+    let hex = val.trim_left_matches('#');
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 => Ok(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+        _ => Err(ConfigError::Syntax(format!("expected a color as RRGGBB, found \"{}\"", val))),
+    }
+}
@@ -91,6 +91,15 @@ impl Drive {
         }
     }
 
+    /* The head goes back to track 0 with the magnets off, but the disk stays in. */
+    pub fn power_on(&mut self) {
+        self.track = 0;
+        self.sector = 15;
+        self.idx = 0;
+        self.magnets = 0;
+        self.phase = 0;
+    }
+
     pub fn add_disk<R>(&mut self, mut disk: R)
         where R: Read
     {
@@ -381,4 +390,12 @@ impl PeripheralCard for DiskII {
     fn read_expansion_rom(&mut self, _addr: u16) -> u8 {
         0
     }
+
+    fn power_on(&mut self) {
+        for drive in self.drives.iter_mut() {
+            drive.power_on();
+        }
+        self.drive_num = 0;
+        self.mode = Mode::Read;
+    }
 }
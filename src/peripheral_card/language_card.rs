@@ -1,4 +1,5 @@
 use peripheral_card::PeripheralCard;
+use ram_init::RamInit;
 
 const WRITE_SWITCH: u16 = 0x0001;
 const READ_SWITCH: u16 = 0x0002;
@@ -7,45 +8,58 @@ const BANK_SWITCH: u16 = 0x0008;
 
 const HIGH_BANK_SIZE: usize = 0x2000;
 const LOW_BANK_SIZE: usize = 0x1000;
-const ROM_SIZE: usize = 0x3000;
+pub const ROM_SIZE: usize = 0x3000;
 
-pub struct LanguageCard {
-    rom: [u8; ROM_SIZE],
-    high_bank: [u8; HIGH_BANK_SIZE],
-    low_bank: [[u8; LOW_BANK_SIZE]; 2],
-    last_write: bool,
-    write: bool,
-    read: bool,
-    bank: usize,
+/* Random fills number card RAM after the Mapper's main and aux banks. */
+const FIRST_FILL_BANK: u64 = 2;
+
+/* The $C080-$C08F switch state shared by language card compatibles.
+ *
+ *   bits 0-1: 00 read RAM, no write
+ *             01 read ROM, write RAM
+ *             10 read ROM, no write
+ *             11 read RAM, write RAM
+ *   bit 3:    clear for $D000 bank 2, set for bank 1
+ *
+ * Writing has to be enabled by two reads of odd switches in a row.
+ * The first sets the pre-write flag and the second enables writing.
+ * Any write access to an odd switch clears pre-write without touching
+ * the write enable, and any access to an even switch clears both.
+ * Read RAM/ROM and the bank follow every access, read or write.
+ */
+pub struct BankSwitch {
+    pre_write: bool,
+    pub write: bool,
+    pub read: bool,
+    pub bank: usize,
 }
 
-impl LanguageCard {
-    pub fn new(rom: [u8; ROM_SIZE]) -> LanguageCard {
-        LanguageCard {
-            rom: rom,
-            high_bank: [0; HIGH_BANK_SIZE],
-            low_bank: [[0; LOW_BANK_SIZE]; 2],
-            last_write: false,
+impl BankSwitch {
+    pub fn new() -> BankSwitch {
+        BankSwitch {
+            pre_write: false,
             write: false,
             read: false,
             bank: 0,
         }
     }
-}
-
-impl PeripheralCard for LanguageCard {
-    fn read_switch(&mut self, switch: u16) -> u8 {
-        self.write = switch & WRITE_SWITCH != 0;
 
+    pub fn access(&mut self, switch: u16, write_access: bool) {
         /* READ == WRITE ? RAM : ROM */
-        if ((switch & READ_SWITCH) != 0) == self.write {
-            if self.last_write {
-                self.read = true;
+        self.read = ((switch & READ_SWITCH) != 0) == ((switch & WRITE_SWITCH) != 0);
+
+        if switch & WRITE_SWITCH != 0 {
+            if write_access {
+                self.pre_write = false;
+            } else {
+                if self.pre_write {
+                    self.write = true;
+                }
+                self.pre_write = true;
             }
-            self.last_write = true;
         } else {
-            self.read = false;
-            self.last_write = false;
+            self.pre_write = false;
+            self.write = false;
         }
 
         if switch & BANK_SWITCH != 0 {
@@ -53,10 +67,94 @@ impl PeripheralCard for LanguageCard {
         } else {
             self.bank = 1;
         }
+    }
+}
+
+/* 16K of RAM laid over $D000-$FFFF, with two 4K banks at $D000. */
+pub struct RamBank {
+    high_bank: [u8; HIGH_BANK_SIZE],
+    low_bank: [[u8; LOW_BANK_SIZE]; 2],
+}
+
+impl RamBank {
+    /* `index` tells the banks of a card apart for random fills. */
+    pub fn new(init: RamInit, index: usize) -> RamBank {
+        let mut ram = RamBank {
+            high_bank: [0; HIGH_BANK_SIZE],
+            low_bank: [[0; LOW_BANK_SIZE]; 2],
+        };
+        let bank = (FIRST_FILL_BANK + index as u64) * 3;
+        init.fill(&mut ram.high_bank, bank);
+        init.fill(&mut ram.low_bank[0], bank + 1);
+        init.fill(&mut ram.low_bank[1], bank + 2);
+        ram
+    }
+
+    pub fn read(&self, switch: &BankSwitch, addr: u16) -> u8 {
+        if addr >= 0xE000 {
+            self.high_bank[(addr - 0xE000) as usize]
+        } else {
+            self.low_bank[switch.bank][(addr - 0xD000) as usize]
+        }
+    }
+
+    pub fn write(&mut self, switch: &BankSwitch, addr: u16, val: u8) {
+        if addr >= 0xE000 {
+            self.high_bank[(addr - 0xE000) as usize] = val;
+        } else {
+            self.low_bank[switch.bank][(addr - 0xD000) as usize] = val;
+        }
+    }
+}
+
+pub struct LanguageCard {
+    rom: [u8; ROM_SIZE],
+    /* main RAM, plus the aux copy on the //e */
+    ram: Vec<RamBank>,
+    ram_init: RamInit,
+    aux: bool,
+    switch: BankSwitch,
+}
 
+impl LanguageCard {
+    pub fn new(rom: [u8; ROM_SIZE], ram_init: RamInit) -> LanguageCard {
+        LanguageCard {
+            rom: rom,
+            ram: vec![RamBank::new(ram_init, 0)],
+            ram_init: ram_init,
+            aux: false,
+            switch: BankSwitch::new(),
+        }
+    }
+
+    /* The //e's built-in language card, with a second set of RAM
+     * in auxiliary memory selected by ALTZP.
+     */
+    pub fn with_aux_bank(rom: [u8; ROM_SIZE], ram_init: RamInit) -> LanguageCard {
+        LanguageCard {
+            rom: rom,
+            ram: vec![RamBank::new(ram_init, 0), RamBank::new(ram_init, 1)],
+            ram_init: ram_init,
+            aux: false,
+            switch: BankSwitch::new(),
+        }
+    }
+
+    fn bank_index(&self) -> usize {
+        if self.aux { 1 } else { 0 }
+    }
+}
+
+impl PeripheralCard for LanguageCard {
+    fn read_switch(&mut self, switch: u16) -> u8 {
+        self.switch.access(switch, false);
         0
     }
 
+    fn write_switch(&mut self, switch: u16, _val: u8) {
+        self.switch.access(switch, true);
+    }
+
     fn read_switch_without_mm(&mut self, _switch: u16) -> u8 {
         0
     }
@@ -70,28 +168,133 @@ impl PeripheralCard for LanguageCard {
     }
 
     fn read_language_rom(&mut self, addr: u16) -> u8 {
-        if self.read {
-            if addr >= 0xE000 {
-                self.high_bank[(addr - 0xE000) as usize]
-            } else {
-                self.low_bank[(self.bank) as usize][(addr - 0xD000) as usize]
-            }
+        if self.switch.read {
+            self.ram[self.bank_index()].read(&self.switch, addr)
         } else {
             self.rom[(addr - 0xD000) as usize]
         }
     }
 
     fn write_language_rom(&mut self, addr: u16, val: u8) {
-        if self.write {
-            if addr >= 0xE000 {
-                self.high_bank[(addr - 0xE000) as usize] = val;
-            } else {
-                self.low_bank[(self.bank) as usize][(addr - 0xD000) as usize] = val;
-            }
+        if self.switch.write {
+            let bank = self.bank_index();
+            self.ram[bank].write(&self.switch, addr, val);
         }
     }
 
     fn is_language_card(&self) -> bool {
         true
     }
+
+    fn power_on(&mut self) {
+        for (index, bank) in self.ram.iter_mut().enumerate() {
+            *bank = RamBank::new(self.ram_init, index);
+        }
+        self.aux = false;
+        self.switch = BankSwitch::new();
+    }
+
+    fn language_bank2(&self) -> bool {
+        self.switch.bank == 1
+    }
+
+    fn language_read_ram(&self) -> bool {
+        self.switch.read
+    }
+
+    fn set_aux_bank(&mut self, aux: bool) {
+        self.aux = aux && self.ram.len() > 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* $C080-$C08F: reads RAM, selects $D000 bank 2, can enable writing */
+    const SWITCHES: [(bool, bool, bool); 16] = [(true, true, false),
+                                                (false, true, true),
+                                                (false, true, false),
+                                                (true, true, true),
+                                                (true, true, false),
+                                                (false, true, true),
+                                                (false, true, false),
+                                                (true, true, true),
+                                                (true, false, false),
+                                                (false, false, true),
+                                                (false, false, false),
+                                                (true, false, true),
+                                                (true, false, false),
+                                                (false, false, true),
+                                                (false, false, false),
+                                                (true, false, true)];
+
+    /* Accesses a switch from power on, true for a write, and checks
+     * read source, bank, write enable and pre-write afterwards.
+     */
+    fn check(switch: u16, accesses: &[bool], write: bool, pre_write: bool) {
+        let (read_ram, bank2, _) = SWITCHES[switch as usize];
+        let mut state = BankSwitch::new();
+        for write_access in accesses {
+            state.access(switch, *write_access);
+        }
+        let name = format!("$C08{:X} {:?}", switch, accesses);
+        assert_eq!(state.read, read_ram, "read RAM after {}", name);
+        assert_eq!(state.bank == 1, bank2, "bank 2 after {}", name);
+        assert_eq!(state.write, write, "write enable after {}", name);
+        assert_eq!(state.pre_write, pre_write, "pre-write after {}", name);
+    }
+
+    #[test]
+    fn truth_table() {
+        for switch in 0..16 {
+            let (_, _, can_write) = SWITCHES[switch as usize];
+            check(switch, &[false], false, can_write);
+            check(switch, &[false, false], can_write, can_write);
+            check(switch, &[false, true], false, false);
+            check(switch, &[true], false, false);
+        }
+    }
+
+    #[test]
+    fn write_access_keeps_write_enable() {
+        for switch in 0..16 {
+            let (_, _, can_write) = SWITCHES[switch as usize];
+            let mut state = BankSwitch::new();
+            state.access(0x3, false);
+            state.access(0x3, false);
+            state.access(switch, true);
+            /* odd switches leave writing on, even ones turn it off */
+            assert_eq!(state.write, can_write, "write enable after writing $C08{:X}", switch);
+            assert!(!state.pre_write);
+        }
+    }
+
+    #[test]
+    fn reads_must_be_consecutive() {
+        let mut state = BankSwitch::new();
+        state.access(0x1, false);
+        state.access(0x0, false);
+        state.access(0x1, false);
+        assert!(!state.write && state.pre_write);
+        state.access(0x9, false);
+        assert!(state.write);
+    }
+
+    #[test]
+    fn power_on_fills_ram_from_init() {
+        let mut card = LanguageCard::new([0; ROM_SIZE], RamInit::Pattern);
+        /* read RAM bank 2, then write enable with $C083 twice */
+        card.read_switch(0x3);
+        card.read_switch(0x3);
+        assert_eq!(card.read_language_rom(0xD000), 0xFF);
+        assert_eq!(card.read_language_rom(0xD002), 0x00);
+        assert_eq!(card.read_language_rom(0xE001), 0xFF);
+        card.write_language_rom(0xD002, 0x55);
+        assert_eq!(card.read_language_rom(0xD002), 0x55);
+
+        card.power_on();
+        card.read_switch(0x0);
+        assert_eq!(card.read_language_rom(0xD002), 0x00);
+    }
 }
@@ -1,8 +1,18 @@
 pub mod language_card;
 pub mod disk;
+pub mod softcard;
+pub mod videoterm;
+pub mod saturn;
+pub mod serial;
 
 pub use self::language_card::LanguageCard;
 pub use self::disk::DiskII;
+pub use self::softcard::SoftCard;
+pub use self::videoterm::Videoterm;
+pub use self::saturn::Saturn128K;
+pub use self::serial::SerialPort;
+
+use r6502::memory::Memory;
 
 /* TODO: with and without mm */
 pub trait PeripheralCard {
@@ -21,8 +31,13 @@ pub trait PeripheralCard {
 
     fn read_rom(&mut self, addr: u16) -> u8;
 
+    fn write_rom(&mut self, _addr: u16, _val: u8) {}
+
+    /* Only called while this card owns $C800-$CFFF. */
     fn read_expansion_rom(&mut self, addr: u16) -> u8;
 
+    fn write_expansion_rom(&mut self, _addr: u16, _val: u8) {}
+
     fn read_language_rom(&mut self, _addr: u16) -> u8 {
         unreachable!()
     }
@@ -34,4 +49,59 @@ pub trait PeripheralCard {
     fn is_language_card(&self) -> bool {
         false
     }
+
+    /* Language card state for the //e's $C011/$C012 status reads. */
+    fn language_bank2(&self) -> bool {
+        false
+    }
+
+    fn language_read_ram(&self) -> bool {
+        false
+    }
+
+    /* The //e's ALTZP switch selects the aux language card RAM. */
+    fn set_aux_bank(&mut self, _aux: bool) {}
+
+    /* Called after every instruction with the cycles it took. */
+    fn tick(&mut self, _cycles: u64) {}
+
+    /* State of the card's IRQ and NMI lines. */
+    fn irq(&self) -> bool {
+        false
+    }
+
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    /* Called when the RESET line is pulled. */
+    fn reset(&mut self) {}
+
+    /* Called on a power cycle, where nothing survives. Cards with
+     * state a reset leaves alone, like RAM, should clear it here.
+     */
+    fn power_on(&mut self) {
+        self.reset();
+    }
+
+    /* Cards that can take the bus away from the 6502. While this is true
+     * the 6502 is halted and run_bus_master() is called instead.
+     */
+    fn bus_request(&self) -> bool {
+        false
+    }
+
+    /* Cards with their own video output, like 80 column cards, draw a
+     * frame of video_size() pixels with one bool per dot.
+     */
+    fn video_size(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    fn draw_video(&self, _frame: &mut [bool]) {}
+
+    /* Drives the bus for about `cycles` cycles, returning how many were used. */
+    fn run_bus_master(&mut self, _memory: &mut Memory<u8>, _cycles: u64) -> u64 {
+        0
+    }
 }
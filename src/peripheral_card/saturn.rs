@@ -0,0 +1,99 @@
+use peripheral_card::PeripheralCard;
+use peripheral_card::language_card::{BankSwitch, RamBank, ROM_SIZE};
+use ram_init::RamInit;
+
+/* Saturn Systems 128K RAM card.
+ *
+ * Behaves like a language card, except that $C084-$C087 and
+ * $C08C-$C08F select which of the eight 16K banks sits behind the
+ * usual language card switches instead of changing their state.
+ */
+
+const NUM_BANKS: usize = 8;
+const SATURN_SWITCH: u16 = 0x0004;
+
+pub struct Saturn128K {
+    rom: [u8; ROM_SIZE],
+    banks: Vec<RamBank>,
+    ram_init: RamInit,
+    bank: usize,
+    switch: BankSwitch,
+}
+
+impl Saturn128K {
+    pub fn new(rom: [u8; ROM_SIZE], ram_init: RamInit) -> Saturn128K {
+        Saturn128K {
+            rom: rom,
+            banks: (0..NUM_BANKS).map(|index| RamBank::new(ram_init, index)).collect(),
+            ram_init: ram_init,
+            bank: 0,
+            switch: BankSwitch::new(),
+        }
+    }
+
+    fn access(&mut self, switch: u16, write_access: bool) {
+        if switch & SATURN_SWITCH != 0 {
+            /* $C084-$C087 pick banks 0-3, $C08C-$C08F banks 4-7 */
+            self.bank = ((switch & 0x3) | ((switch & 0x8) >> 1)) as usize;
+        } else {
+            self.switch.access(switch, write_access);
+        }
+    }
+}
+
+impl PeripheralCard for Saturn128K {
+    fn read_switch(&mut self, switch: u16) -> u8 {
+        self.access(switch, false);
+        0
+    }
+
+    fn write_switch(&mut self, switch: u16, _val: u8) {
+        self.access(switch, true);
+    }
+
+    fn read_switch_without_mm(&mut self, _switch: u16) -> u8 {
+        0
+    }
+
+    fn read_rom(&mut self, _addr: u16) -> u8 {
+        unreachable!()
+    }
+
+    fn read_expansion_rom(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn read_language_rom(&mut self, addr: u16) -> u8 {
+        if self.switch.read {
+            self.banks[self.bank].read(&self.switch, addr)
+        } else {
+            self.rom[(addr - 0xD000) as usize]
+        }
+    }
+
+    fn write_language_rom(&mut self, addr: u16, val: u8) {
+        if self.switch.write {
+            self.banks[self.bank].write(&self.switch, addr, val);
+        }
+    }
+
+    fn is_language_card(&self) -> bool {
+        true
+    }
+
+    fn power_on(&mut self) {
+        for (index, bank) in self.banks.iter_mut().enumerate() {
+            *bank = RamBank::new(self.ram_init, index);
+        }
+        self.bank = 0;
+        self.switch = BankSwitch::new();
+    }
+
+    fn language_bank2(&self) -> bool {
+        self.switch.bank == 1
+    }
+
+    fn language_read_ram(&self) -> bool {
+        self.switch.read
+    }
+}
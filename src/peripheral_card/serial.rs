@@ -0,0 +1,96 @@
+use peripheral_card::PeripheralCard;
+
+/* One of the //c's 6551 ACIA serial ports, at $C0n8-$C0nB of its
+ * virtual slot. Its firmware is in the //c rom, not on the card.
+ *
+ * Nothing is connected to the far end: transmitted bytes go to the
+ * log a line at a time and nothing is ever received.
+ */
+
+const REG_DATA: u16 = 0x8;
+const REG_STATUS: u16 = 0x9;
+const REG_COMMAND: u16 = 0xA;
+const REG_CONTROL: u16 = 0xB;
+
+const STATUS_TX_EMPTY: u8 = 0x10;
+const STATUS_IRQ: u8 = 0x80;
+
+/* command bits 2-3 == 01: transmit interrupts on */
+const COMMAND_TX_MASK: u8 = 0x0C;
+const COMMAND_TX_IRQ: u8 = 0x04;
+
+pub struct SerialPort {
+    port: usize,
+    command: u8,
+    control: u8,
+    line: Vec<u8>,
+}
+
+impl SerialPort {
+    pub fn new(port: usize) -> SerialPort {
+        SerialPort {
+            port: port,
+            command: 0,
+            control: 0,
+            line: Vec::new(),
+        }
+    }
+
+    /* the transmitter is always ready, so this holds while enabled */
+    fn tx_irq(&self) -> bool {
+        self.command & COMMAND_TX_MASK == COMMAND_TX_IRQ
+    }
+
+    fn transmit(&mut self, val: u8) {
+        let ch = val & 0x7F;
+        if ch == b'\r' || ch == b'\n' {
+            info!("serial port {}: {}", self.port, String::from_utf8_lossy(&self.line));
+            self.line.clear();
+        } else {
+            self.line.push(ch);
+        }
+    }
+}
+
+impl PeripheralCard for SerialPort {
+    fn read_switch_without_mm(&mut self, switch: u16) -> u8 {
+        match switch {
+            REG_DATA => 0,
+            REG_STATUS => {
+                let irq = if self.tx_irq() { STATUS_IRQ } else { 0 };
+                irq | STATUS_TX_EMPTY
+            }
+            REG_COMMAND => self.command,
+            REG_CONTROL => self.control,
+            _ => 0,
+        }
+    }
+
+    fn write_switch_without_mm(&mut self, switch: u16, val: u8) {
+        match switch {
+            REG_DATA => self.transmit(val),
+            /* writing the status register is a programmed reset */
+            REG_STATUS => self.command &= 0xE0,
+            REG_COMMAND => self.command = val,
+            REG_CONTROL => self.control = val,
+            _ => {}
+        }
+    }
+
+    fn read_rom(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn read_expansion_rom(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn irq(&self) -> bool {
+        self.tx_irq()
+    }
+
+    fn reset(&mut self) {
+        self.command = 0;
+        self.control = 0;
+    }
+}
@@ -0,0 +1,119 @@
+use peripheral_card::PeripheralCard;
+use z80::Z80;
+
+use r6502::memory::Memory;
+
+/* Microsoft Z-80 SoftCard.
+ *
+ * Writing anything to the card's $Cn00 page hands the bus from the 6502
+ * to the Z-80, and the Z-80 writing to the same page hands it back. The
+ * Z-80 sees Apple memory rearranged so that CP/M gets contiguous RAM
+ * starting at $0000:
+ *
+ *   Z-80           Apple
+ *   $0000-$AFFF    $1000-$BFFF
+ *   $B000-$DFFF    $D000-$FFFF
+ *   $E000-$EFFF    $C000-$CFFF
+ *   $F000-$FFFF    $0000-$0FFF
+ */
+
+/* The Z-80 runs at about twice the 6502 clock. */
+const Z80_CLOCKS_PER_CYCLE: u64 = 2;
+
+fn translate(addr: u16) -> u16 {
+    match addr {
+        0x0000...0xAFFF => addr + 0x1000,
+        0xB000...0xDFFF => addr + 0x2000,
+        0xE000...0xEFFF => addr - 0x2000,
+        _ => addr - 0xF000,
+    }
+}
+
+/* The Apple bus as seen from the Z-80. */
+struct SoftCardBus<'m> {
+    memory: &'m mut Memory<u8>,
+    switch_page: u16,
+    released: bool,
+}
+
+impl<'m> Memory<u8> for SoftCardBus<'m> {
+    fn read_without_mm(&mut self, addr: u16) -> u8 {
+        self.memory.read(translate(addr))
+    }
+
+    fn write_without_mm(&mut self, addr: u16, val: u8) {
+        let addr = translate(addr);
+        /* The card is out of its slot while it owns the bus,
+         * so it has to catch its own switch here.
+         */
+        if addr & 0xFF00 == self.switch_page {
+            self.released = true;
+        }
+        self.memory.write(addr, val);
+    }
+}
+
+pub struct SoftCard {
+    slot: usize,
+    z80: Z80,
+    z80_active: bool,
+}
+
+impl SoftCard {
+    pub fn new(slot: usize) -> SoftCard {
+        SoftCard {
+            slot: slot,
+            z80: Z80::new(),
+            z80_active: false,
+        }
+    }
+}
+
+impl PeripheralCard for SoftCard {
+    fn read_switch_without_mm(&mut self, _switch: u16) -> u8 {
+        0
+    }
+
+    fn read_rom(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn write_rom(&mut self, _addr: u16, _val: u8) {
+        info!("SoftCard taking the bus");
+        self.z80_active = true;
+    }
+
+    fn read_expansion_rom(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn reset(&mut self) {
+        self.z80.reset();
+        self.z80_active = false;
+    }
+
+    fn bus_request(&self) -> bool {
+        self.z80_active
+    }
+
+    fn run_bus_master(&mut self, memory: &mut Memory<u8>, cycles: u64) -> u64 {
+        let mut bus = SoftCardBus {
+            memory: memory,
+            switch_page: 0xC000 | ((self.slot as u16) << 8),
+            released: false,
+        };
+
+        let budget = cycles * Z80_CLOCKS_PER_CYCLE;
+        let mut clocks = 0;
+        while clocks < budget {
+            clocks += self.z80.step(&mut bus) as u64;
+            if bus.released {
+                info!("SoftCard releasing the bus");
+                self.z80_active = false;
+                break;
+            }
+        }
+
+        (clocks + Z80_CLOCKS_PER_CYCLE - 1) / Z80_CLOCKS_PER_CYCLE
+    }
+}
@@ -0,0 +1,167 @@
+use peripheral_card::PeripheralCard;
+
+/* Videx Videoterm 80 column card.
+ *
+ * $C0n0/$C0n1 are the 6845 CRTC address and data registers. Bits 2-3 of
+ * any $C0nX address also pick which 512 byte quarter of the 2K screen
+ * RAM shows up at $CC00-$CDFF. The 1K firmware rom is at $C800-$CBFF,
+ * with its last page mirrored at $Cn00.
+ *
+ * Characters are 8x9 dots, giving an 80x24 screen of 640x216.
+ */
+
+pub const ROM_SIZE: usize = 0x400;
+pub const CHAR_ROM_SIZE: usize = 0x800;
+const SCREEN_RAM_SIZE: usize = 0x800;
+const SCREEN_BANK_SIZE: usize = 0x200;
+
+const COLUMNS: usize = 80;
+const ROWS: usize = 24;
+const CHAR_WIDTH: usize = 8;
+const CHAR_HEIGHT: usize = 9;
+/* each glyph takes 16 bytes in the character rom */
+const CHAR_ROM_STRIDE: usize = 16;
+
+const CRTC_REGISTERS: usize = 18;
+const CRTC_CURSOR_START: usize = 10;
+const CRTC_CURSOR_END: usize = 11;
+const CRTC_START_HI: usize = 12;
+const CRTC_START_LO: usize = 13;
+const CRTC_CURSOR_HI: usize = 14;
+const CRTC_CURSOR_LO: usize = 15;
+
+/* 6502 cycles per video field */
+const CYCLES_PER_FIELD: u64 = 17030;
+
+pub struct Videoterm {
+    rom: Vec<u8>,
+    char_rom: Vec<u8>,
+    ram: [u8; SCREEN_RAM_SIZE],
+    bank: usize,
+    crtc_addr: usize,
+    crtc: [u8; CRTC_REGISTERS],
+    cycles: u64,
+}
+
+impl Videoterm {
+    pub fn new(rom: Vec<u8>, char_rom: Vec<u8>) -> Videoterm {
+        assert_eq!(rom.len(), ROM_SIZE);
+        assert_eq!(char_rom.len(), CHAR_ROM_SIZE);
+        Videoterm {
+            rom: rom,
+            char_rom: char_rom,
+            ram: [0; SCREEN_RAM_SIZE],
+            bank: 0,
+            crtc_addr: 0,
+            crtc: [0; CRTC_REGISTERS],
+            cycles: 0,
+        }
+    }
+
+    fn crtc_pair(&self, hi: usize, lo: usize) -> usize {
+        (((self.crtc[hi] & 0x3F) as usize) << 8) | self.crtc[lo] as usize
+    }
+
+    fn cursor_visible(&self) -> bool {
+        let field = self.cycles / CYCLES_PER_FIELD;
+        /* bits 5-6 of R10: steady, off, blink at 1/16 or 1/32 field rate */
+        match (self.crtc[CRTC_CURSOR_START] >> 5) & 0x3 {
+            0 => true,
+            1 => false,
+            2 => field & 0x08 != 0,
+            _ => field & 0x10 != 0,
+        }
+    }
+}
+
+impl PeripheralCard for Videoterm {
+    fn read_switch(&mut self, switch: u16) -> u8 {
+        self.bank = ((switch >> 2) & 0x3) as usize;
+        self.read_switch_without_mm(switch)
+    }
+
+    fn write_switch(&mut self, switch: u16, val: u8) {
+        self.bank = ((switch >> 2) & 0x3) as usize;
+        if switch & 0x1 == 0 {
+            self.crtc_addr = (val & 0x1F) as usize;
+        } else if self.crtc_addr < CRTC_REGISTERS {
+            self.crtc[self.crtc_addr] = val;
+        }
+    }
+
+    fn read_switch_without_mm(&mut self, switch: u16) -> u8 {
+        /* only the cursor and light pen registers can be read back */
+        if switch & 0x1 != 0 && self.crtc_addr >= CRTC_CURSOR_HI && self.crtc_addr < CRTC_REGISTERS {
+            self.crtc[self.crtc_addr]
+        } else {
+            0
+        }
+    }
+
+    fn read_rom(&mut self, addr: u16) -> u8 {
+        self.rom[0x300 | (addr & 0xFF) as usize]
+    }
+
+    fn read_expansion_rom(&mut self, addr: u16) -> u8 {
+        match addr {
+            0xC800...0xCBFF => self.rom[(addr - 0xC800) as usize],
+            0xCC00...0xCDFF => self.ram[self.bank * SCREEN_BANK_SIZE + (addr - 0xCC00) as usize],
+            _ => 0,
+        }
+    }
+
+    fn write_expansion_rom(&mut self, addr: u16, val: u8) {
+        if let 0xCC00...0xCDFF = addr {
+            self.ram[self.bank * SCREEN_BANK_SIZE + (addr - 0xCC00) as usize] = val;
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+    }
+
+    fn power_on(&mut self) {
+        self.ram = [0; SCREEN_RAM_SIZE];
+        self.bank = 0;
+        self.crtc_addr = 0;
+        self.crtc = [0; CRTC_REGISTERS];
+    }
+
+    fn video_size(&self) -> Option<(usize, usize)> {
+        Some((COLUMNS * CHAR_WIDTH, ROWS * CHAR_HEIGHT))
+    }
+
+    fn draw_video(&self, frame: &mut [bool]) {
+        let start = self.crtc_pair(CRTC_START_HI, CRTC_START_LO);
+        let cursor = self.crtc_pair(CRTC_CURSOR_HI, CRTC_CURSOR_LO) & (SCREEN_RAM_SIZE - 1);
+        let cursor_start = (self.crtc[CRTC_CURSOR_START] & 0x1F) as usize;
+        let cursor_end = (self.crtc[CRTC_CURSOR_END] & 0x1F) as usize;
+        let cursor_visible = self.cursor_visible();
+        let width = COLUMNS * CHAR_WIDTH;
+
+        for row in 0..ROWS {
+            for col in 0..COLUMNS {
+                let addr = (start + row * COLUMNS + col) & (SCREEN_RAM_SIZE - 1);
+                let character = self.ram[addr];
+                let glyph = (character & 0x7F) as usize * CHAR_ROM_STRIDE;
+                /* the standard character rom uses bit 7 for inverse */
+                let inverse = character & 0x80 != 0;
+
+                for line in 0..CHAR_HEIGHT {
+                    let mut bits = self.char_rom[glyph + line];
+                    if inverse {
+                        bits = !bits;
+                    }
+                    if cursor_visible && addr == cursor && line >= cursor_start && line <= cursor_end {
+                        bits = !bits;
+                    }
+
+                    let base = (row * CHAR_HEIGHT + line) * width + col * CHAR_WIDTH;
+                    for dot in 0..CHAR_WIDTH {
+                        frame[base + dot] = bits & (0x80 >> dot) != 0;
+                    }
+                }
+            }
+        }
+    }
+}
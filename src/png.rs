@@ -0,0 +1,145 @@
+use std::io::{self, Write};
+
+use rom::crc32;
+
+/* Just enough PNG to save RGBA pictures.
+ *
+ * The image data is kept in stored (uncompressed) deflate blocks,
+ * which every decoder reads and which needs no compressor. Files come
+ * out about the size of the raw pixels.
+ */
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+/* a stored deflate block holds at most this much */
+const MAX_STORED: usize = 0xFFFF;
+
+pub fn write_rgba<W: Write>(out: &mut W,
+                            width: usize,
+                            height: usize,
+                            pixels: &[u8])
+                            -> io::Result<()> {
+    try!(out.write_all(&SIGNATURE));
+    try!(write_chunk(out, b"IHDR", &header(width, height)));
+    try!(write_chunk(out, b"IDAT", &image_data(width, height, pixels)));
+    write_chunk(out, b"IEND", &[])
+}
+
+/* IHDR for 8 bit RGBA, no interlacing. */
+pub fn header(width: usize, height: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    push_u32(&mut data, width as u32);
+    push_u32(&mut data, height as u32);
+    data.extend_from_slice(&[8, 6, 0, 0, 0]);
+    data
+}
+
+/* The zlib stream for a picture, each row unfiltered. */
+pub fn image_data(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let pitch = width * 4;
+    let mut raw = Vec::with_capacity((pitch + 1) * height);
+    for row in pixels[..pitch * height].chunks(pitch) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    zlib_stored(&raw)
+}
+
+pub fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    push_u32(&mut chunk, data.len() as u32);
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    /* the crc covers the type and data but not the length */
+    let crc = crc32(&chunk[4..]);
+    push_u32(&mut chunk, crc);
+    out.write_all(&chunk)
+}
+
+pub fn push_u32(data: &mut Vec<u8>, val: u32) {
+    data.extend_from_slice(&[(val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8]);
+}
+
+fn zlib_stored(raw: &[u8]) -> Vec<u8> {
+    /* deflate with a 32K window, no dictionary, fastest level */
+    let mut data = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(MAX_STORED).peekable();
+    if blocks.peek().is_none() {
+        /* an empty stream still needs its final block */
+        data.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        data.push(if last { 0x01 } else { 0x00 });
+        data.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        data.extend_from_slice(block);
+    }
+    push_u32(&mut data, adler32(raw));
+    data
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum(raw: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        push_u32(&mut data, adler32(raw));
+        data
+    }
+
+    #[test]
+    fn adler32_check_value() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn empty_chunk() {
+        let mut out = Vec::new();
+        write_chunk(&mut out, b"IEND", &[]).unwrap();
+        assert_eq!(out, [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+
+    #[test]
+    fn empty_stream_has_a_final_block() {
+        assert_eq!(zlib_stored(&[]),
+                   [0x78, 0x01, 0x01, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn stored_blocks_split_past_0xffff() {
+        /* just fits in one block */
+        let raw = vec![0x5A; MAX_STORED];
+        let data = zlib_stored(&raw);
+        assert_eq!(data.len(), 2 + 5 + MAX_STORED + 4);
+        assert_eq!(data[2..7], [0x01, 0xFF, 0xFF, 0x00, 0x00]);
+        assert_eq!(data[data.len() - 4..], checksum(&raw)[..]);
+
+        /* one byte more spills into a second, final block */
+        let raw = vec![0x5A; MAX_STORED + 1];
+        let data = zlib_stored(&raw);
+        assert_eq!(data.len(), 2 + 5 + MAX_STORED + 5 + 1 + 4);
+        assert_eq!(data[2..7], [0x00, 0xFF, 0xFF, 0x00, 0x00]);
+        let second = 7 + MAX_STORED;
+        assert_eq!(data[second..second + 6], [0x01, 0x01, 0x00, 0xFE, 0xFF, 0x5A]);
+        assert_eq!(data[data.len() - 4..], checksum(&raw)[..]);
+    }
+
+    #[test]
+    fn rows_are_unfiltered() {
+        let pixels = [1, 2, 3, 4, 5, 6, 7, 8];
+        let data = image_data(1, 2, &pixels);
+        assert_eq!(data[7..17], [0, 1, 2, 3, 4, 0, 5, 6, 7, 8]);
+    }
+}
@@ -0,0 +1,71 @@
+/* What RAM holds at power on.
+ *
+ * Real RAM powers up in a characteristic state rather than zeroed, and
+ * software that forgets to clear memory can behave differently there.
+ * The pattern is the FF FF 00 00 repeat most machines come up with;
+ * random fills come from a seed so a run can be repeated exactly.
+ */
+
+const DEFAULT_SEED: u64 = 0x6502_6502_6502_6502;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RamInit {
+    Zero,
+    Pattern,
+    Random(u64),
+}
+
+impl RamInit {
+    /* "zero", "pattern", "random" or "random:SEED" */
+    pub fn from_spec(spec: &str) -> Option<RamInit> {
+        let mut parts = spec.trim().splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("zero"), None) => Some(RamInit::Zero),
+            (Some("pattern"), None) => Some(RamInit::Pattern),
+            (Some("random"), None) => Some(RamInit::Random(DEFAULT_SEED)),
+            (Some("random"), Some(seed)) => seed.trim().parse().ok().map(RamInit::Random),
+            _ => None,
+        }
+    }
+
+    /* `bank` keeps separate banks of RAM from getting the same random fill. */
+    pub fn fill(&self, ram: &mut [u8], bank: u64) {
+        match *self {
+            RamInit::Zero => {
+                for byte in ram.iter_mut() {
+                    *byte = 0x00;
+                }
+            }
+            RamInit::Pattern => {
+                for (addr, byte) in ram.iter_mut().enumerate() {
+                    *byte = if addr & 0x2 == 0 { 0xFF } else { 0x00 };
+                }
+            }
+            RamInit::Random(seed) => {
+                let mut rng = XorShift::new(seed ^ bank.wrapping_mul(0x9E3779B97F4A7C15));
+                for byte in ram.iter_mut() {
+                    *byte = rng.next() as u8;
+                }
+            }
+        }
+    }
+}
+
+/* xorshift64*, plenty for filling memory */
+struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        /* the state must never be zero */
+        XorShift { state: if seed == 0 { DEFAULT_SEED } else { seed } }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 32
+    }
+}
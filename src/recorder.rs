@@ -0,0 +1,571 @@
+use monitor::scale;
+use png;
+use scanner::{CYCLES_PER_FRAME, FRAME_RATE};
+use video_output::Frame;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/* Captures every frame the beam draws, and the speaker, to files.
+ *
+ * Timing comes from emulated cycles rather than the clock on the wall,
+ * so a recording plays back at the machine's own speed however fast
+ * the host kept up. Frames are recorded at the window's 560x384 whatever
+ * they came from, so an 80 column card's picture fits too.
+ *
+ * An animated PNG suits short clips: a frame that didn't change only
+ * lengthens the one before, but the pixels aren't compressed. Longer
+ * captures go to a raw YUV4MPEG2 stream with a WAV of the speaker
+ * alongside, for an encoder to take from there.
+ */
+
+pub const RECORD_WIDTH: usize = 560;
+pub const RECORD_HEIGHT: usize = 384;
+const RECORD_PITCH: usize = RECORD_WIDTH * 4;
+
+const SAMPLE_RATE: u64 = 44100;
+const SPEAKER_LEVEL: i16 = 0x2000;
+/* APNG frame delays are in milliseconds and have to fit in 16 bits */
+const MAX_DELAY: u64 = 0xFFFF;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Apng,
+    Y4m,
+}
+
+impl Format {
+    /* By extension: .y4m for the raw stream, .png or .apng for a clip. */
+    pub fn from_path(path: &str) -> Option<Format> {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("png") | Some("apng") => Some(Format::Apng),
+            Some("y4m") => Some(Format::Y4m),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Format::Apng => "png",
+            Format::Y4m => "y4m",
+        }
+    }
+}
+
+pub struct Recorder {
+    video: Video,
+    /* the speaker, with a raw stream only */
+    audio: Option<Wav>,
+    /* the last frame recorded, scaled */
+    last: Vec<u8>,
+}
+
+enum Video {
+    Apng(Apng),
+    Y4m(BufWriter<File>),
+}
+
+impl Recorder {
+    /* Starts a recording at `path`. A raw stream's audio goes next to
+     * it, with .wav in place of its extension.
+     */
+    pub fn new(path: &str, format: Format) -> io::Result<Recorder> {
+        let (video, audio) = match format {
+            Format::Apng => (Video::Apng(try!(Apng::new(path))), None),
+            Format::Y4m => {
+                let mut out = BufWriter::new(try!(File::create(path)));
+                try!(write!(out,
+                            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444\n",
+                            RECORD_WIDTH,
+                            RECORD_HEIGHT,
+                            FRAME_RATE.0,
+                            FRAME_RATE.1));
+                let wav_path = Path::new(path).with_extension("wav");
+                (Video::Y4m(out), Some(try!(Wav::new(&wav_path))))
+            }
+        };
+        Ok(Recorder {
+            video: video,
+            audio: audio,
+            last: Vec::new(),
+        })
+    }
+
+    /* Records the frame the beam finished on the given cycle. */
+    pub fn frame(&mut self, frame: &Frame, cycles: u64) -> io::Result<()> {
+        let pixels = scale(frame.pixels, frame.width, frame.height, RECORD_WIDTH, RECORD_HEIGHT);
+        if let Some(ref mut audio) = self.audio {
+            audio.start(cycles);
+        }
+        match self.video {
+            Video::Apng(ref mut apng) => try!(apng.frame(&pixels, &self.last)),
+            Video::Y4m(ref mut out) => {
+                try!(out.write_all(b"FRAME\n"));
+                try!(out.write_all(&ycbcr(&pixels)));
+            }
+        }
+        self.last = pixels;
+        Ok(())
+    }
+
+    /* The speaker's state as of the given cycle. */
+    pub fn speaker(&mut self, cycles: u64, level: bool) -> io::Result<()> {
+        match self.audio {
+            Some(ref mut audio) => audio.speaker(cycles, level),
+            None => Ok(()),
+        }
+    }
+
+    /* Writes out what is left and fills in the sizes the headers
+     * couldn't know at the start.
+     */
+    pub fn finish(self) -> io::Result<()> {
+        match self.video {
+            Video::Apng(apng) => try!(apng.finish()),
+            Video::Y4m(mut out) => try!(out.flush()),
+        }
+        match self.audio {
+            Some(audio) => audio.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+/* A frame waiting to learn how long it stays up: rows `y` on of the
+ * picture, first shown on frame `start`.
+ */
+struct Pending {
+    y: usize,
+    pixels: Vec<u8>,
+    start: u64,
+    frames: u64,
+}
+
+struct Apng {
+    out: BufWriter<File>,
+    /* where the acTL chunk is, to fill in the frame count at the end */
+    actl_at: u64,
+    /* fcTL and fdAT chunks share one sequence */
+    sequence: u32,
+    frames_written: u32,
+    frames_seen: u64,
+    pending: Option<Pending>,
+}
+
+impl Apng {
+    fn new(path: &str) -> io::Result<Apng> {
+        let mut out = BufWriter::new(try!(File::create(path)));
+        try!(out.write_all(&png::SIGNATURE));
+        try!(png::write_chunk(&mut out, b"IHDR", &png::header(RECORD_WIDTH, RECORD_HEIGHT)));
+        let actl_at = try!(out.seek(SeekFrom::Current(0)));
+        try!(png::write_chunk(&mut out, b"acTL", &animation_control(0)));
+        Ok(Apng {
+            out: out,
+            actl_at: actl_at,
+            sequence: 0,
+            frames_written: 0,
+            frames_seen: 0,
+            pending: None,
+        })
+    }
+
+    /* Only the rows that changed since `last` become a new frame, and
+     * a frame with none just keeps the one before up longer.
+     */
+    fn frame(&mut self, pixels: &[u8], last: &[u8]) -> io::Result<()> {
+        let frame = self.frames_seen;
+        self.frames_seen += 1;
+
+        let changed = |y: &usize| {
+            let row = y * RECORD_PITCH..(y + 1) * RECORD_PITCH;
+            last.len() != pixels.len() || pixels[row.clone()] != last[row]
+        };
+        let first = (0..RECORD_HEIGHT).find(&changed);
+        let end = (0..RECORD_HEIGHT).rev().find(&changed).map(|y| y + 1);
+
+        let (y, end) = match (first, end) {
+            (Some(first), Some(end)) => (first, end),
+            _ => {
+                let too_long = match self.pending {
+                    Some(ref pending) => delay(pending.start, pending.frames + 1) > MAX_DELAY,
+                    None => false,
+                };
+                if !too_long {
+                    if let Some(ref mut pending) = self.pending {
+                        pending.frames += 1;
+                    }
+                    return Ok(());
+                }
+                /* start over with a row that is the same as before */
+                (0, 1)
+            }
+        };
+
+        try!(self.flush());
+        self.pending = Some(Pending {
+            y: y,
+            pixels: pixels[y * RECORD_PITCH..end * RECORD_PITCH].to_vec(),
+            start: frame,
+            frames: 1,
+        });
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let height = pending.pixels.len() / RECORD_PITCH;
+
+        let mut control = Vec::new();
+        png::push_u32(&mut control, self.sequence);
+        png::push_u32(&mut control, RECORD_WIDTH as u32);
+        png::push_u32(&mut control, height as u32);
+        png::push_u32(&mut control, 0);
+        png::push_u32(&mut control, pending.y as u32);
+        let delay = delay(pending.start, pending.frames);
+        control.extend_from_slice(&[(delay >> 8) as u8, delay as u8, 0x03, 0xE8]);
+        /* leave the frame in place, and replace what was under it */
+        control.extend_from_slice(&[0, 0]);
+        try!(png::write_chunk(&mut self.out, b"fcTL", &control));
+        self.sequence += 1;
+
+        let image = png::image_data(RECORD_WIDTH, height, &pending.pixels);
+        if self.frames_written == 0 {
+            /* the first frame doubles as the still picture */
+            try!(png::write_chunk(&mut self.out, b"IDAT", &image));
+        } else {
+            let mut data = Vec::with_capacity(image.len() + 4);
+            png::push_u32(&mut data, self.sequence);
+            data.extend_from_slice(&image);
+            try!(png::write_chunk(&mut self.out, b"fdAT", &data));
+            self.sequence += 1;
+        }
+        self.frames_written += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if self.frames_written == 0 && self.pending.is_none() {
+            /* stopped before a frame came, but a PNG needs a picture */
+            self.pending = Some(Pending {
+                y: 0,
+                pixels: vec![0; RECORD_PITCH * RECORD_HEIGHT],
+                start: 0,
+                frames: 1,
+            });
+        }
+        try!(self.flush());
+        try!(png::write_chunk(&mut self.out, b"IEND", &[]));
+        try!(self.out.seek(SeekFrom::Start(self.actl_at)));
+        try!(png::write_chunk(&mut self.out, b"acTL", &animation_control(self.frames_written)));
+        self.out.flush()
+    }
+}
+
+/* The frame count, then how many times to play (0 is forever). */
+fn animation_control(frames: u32) -> Vec<u8> {
+    let mut data = Vec::new();
+    png::push_u32(&mut data, frames);
+    png::push_u32(&mut data, 0);
+    data
+}
+
+/* How long a frame shown from frame `start` for `frames` frames stays
+ * up, in milliseconds. Rounding each end rather than the length keeps
+ * the clip from drifting against emulated time.
+ */
+fn delay(start: u64, frames: u64) -> u64 {
+    let ms = |frame: u64| (frame * 1000 * FRAME_RATE.1 + FRAME_RATE.0 / 2) / FRAME_RATE.0;
+    ms(start + frames) - ms(start)
+}
+
+/* RGBA to the planes of full resolution BT.601 YCbCr. */
+fn ycbcr(pixels: &[u8]) -> Vec<u8> {
+    let count = pixels.len() / 4;
+    let mut planes = vec![0; count * 3];
+    for (i, pixel) in pixels.chunks(4).enumerate() {
+        let (r, g, b) = (pixel[0] as i32, pixel[1] as i32, pixel[2] as i32);
+        planes[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        planes[count + i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        planes[count * 2 + i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+    planes
+}
+
+/* 16 bit mono PCM of the speaker, a square wave from its toggling. */
+struct Wav {
+    out: BufWriter<File>,
+    /* the cycle the first frame was recorded on, where sound starts */
+    start: Option<u64>,
+    level: bool,
+    samples: u64,
+    /* samples per cycle, as a fraction */
+    rate: (u64, u64),
+}
+
+impl Wav {
+    fn new(path: &Path) -> io::Result<Wav> {
+        let mut out = BufWriter::new(try!(File::create(path)));
+        try!(out.write_all(&wav_header(0)));
+
+        let samples = SAMPLE_RATE * FRAME_RATE.1;
+        let cycles = CYCLES_PER_FRAME * FRAME_RATE.0;
+        let divisor = gcd(samples, cycles);
+        Ok(Wav {
+            out: out,
+            start: None,
+            level: false,
+            samples: 0,
+            rate: (samples / divisor, cycles / divisor),
+        })
+    }
+
+    fn start(&mut self, cycles: u64) {
+        if self.start.is_none() {
+            self.start = Some(cycles);
+        }
+    }
+
+    /* Fills in samples at the old level up to `cycles`, then takes up
+     * the new one. Toggles land up to an instruction late, well under
+     * a sample.
+     */
+    fn speaker(&mut self, cycles: u64, level: bool) -> io::Result<()> {
+        let start = match self.start {
+            Some(start) if cycles > start => start,
+            _ => return Ok(()),
+        };
+        let due = (cycles - start) * self.rate.0 / self.rate.1;
+        let sample = if self.level { SPEAKER_LEVEL } else { -SPEAKER_LEVEL };
+        while self.samples < due {
+            try!(self.out.write_all(&[sample as u8, (sample >> 8) as u8]));
+            self.samples += 1;
+        }
+        self.level = level;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        try!(self.out.seek(SeekFrom::Start(0)));
+        try!(self.out.write_all(&wav_header(self.samples as u32 * 2)));
+        self.out.flush()
+    }
+}
+
+fn wav_header(data_size: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    push_u32_le(&mut header, 36 + data_size);
+    header.extend_from_slice(b"WAVEfmt ");
+    push_u32_le(&mut header, 16);
+    /* PCM, one channel */
+    header.extend_from_slice(&[1, 0, 1, 0]);
+    push_u32_le(&mut header, SAMPLE_RATE as u32);
+    push_u32_le(&mut header, SAMPLE_RATE as u32 * 2);
+    /* two bytes a sample, 16 bits */
+    header.extend_from_slice(&[2, 0, 16, 0]);
+    header.extend_from_slice(b"data");
+    push_u32_le(&mut header, data_size);
+    header
+}
+
+fn push_u32_le(data: &mut Vec<u8>, val: u32) {
+    data.extend_from_slice(&[val as u8, (val >> 8) as u8, (val >> 16) as u8, (val >> 24) as u8]);
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+
+    fn temp_path(name: &str) -> String {
+        env::temp_dir().join(name).to_str().unwrap().to_string()
+    }
+
+    fn read_file(path: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        let _ = fs::remove_file(path);
+        data
+    }
+
+    fn word(data: &[u8], at: usize) -> u32 {
+        (data[at] as u32) << 24 | (data[at + 1] as u32) << 16 | (data[at + 2] as u32) << 8 |
+        data[at + 3] as u32
+    }
+
+    fn word_le(data: &[u8], at: usize) -> u32 {
+        (data[at + 3] as u32) << 24 | (data[at + 2] as u32) << 16 | (data[at + 1] as u32) << 8 |
+        data[at] as u32
+    }
+
+    /* Splits a PNG into its chunks, checking each one's crc. */
+    fn chunks(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(data[..8], png::SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut at = 8;
+        while at < data.len() {
+            let len = word(data, at) as usize;
+            let kind = String::from_utf8(data[at + 4..at + 8].to_vec()).unwrap();
+            assert_eq!(word(data, at + 8 + len), ::rom::crc32(&data[at + 4..at + 8 + len]));
+            chunks.push((kind, data[at + 8..at + 8 + len].to_vec()));
+            at += len + 12;
+        }
+        chunks
+    }
+
+    /* An fcTL chunk's sequence number, height, y and delay. */
+    type Control = (u32, u32, u32, u64);
+
+    /* The kinds of the chunks, and what the fcTL ones hold. */
+    fn frames(chunks: &[(String, Vec<u8>)]) -> (Vec<&str>, Vec<Control>) {
+        let kinds = chunks.iter().map(|chunk| &chunk.0[..]).collect();
+        let controls = chunks.iter()
+            .filter(|chunk| chunk.0 == "fcTL")
+            .map(|chunk| {
+                let data = &chunk.1;
+                assert_eq!(word(data, 4), RECORD_WIDTH as u32);
+                assert_eq!(data[22..24], [0x03, 0xE8]);
+                let delay = (data[20] as u64) << 8 | data[21] as u64;
+                (word(data, 0), word(data, 8), word(data, 16), delay)
+            })
+            .collect();
+        (kinds, controls)
+    }
+
+    fn picture(shade: u8) -> Vec<u8> {
+        vec![shade; RECORD_PITCH * RECORD_HEIGHT]
+    }
+
+    #[test]
+    fn delays_follow_emulated_time() {
+        /* a 60th of a second comes out a little longer than 16ms */
+        assert_eq!(delay(0, 1), 17);
+        assert_eq!(delay(0, 60), 1001);
+        /* frame by frame adds up to the whole */
+        let total: u64 = (0..60).map(|frame| delay(frame, 1)).sum();
+        assert_eq!(total, delay(0, 60));
+        assert_eq!(delay(10, 5) + delay(15, 7), delay(10, 12));
+    }
+
+    #[test]
+    fn apng_frames_only_what_changed() {
+        let path = temp_path("recorder-test-changed.png");
+        let mut apng = Apng::new(&path).unwrap();
+        let first = picture(0x11);
+        let mut second = first.clone();
+        for byte in second[10 * RECORD_PITCH..20 * RECORD_PITCH].iter_mut() {
+            *byte = 0x22;
+        }
+        apng.frame(&first, &[]).unwrap();
+        apng.frame(&first, &first).unwrap();
+        apng.frame(&first, &first).unwrap();
+        apng.frame(&second, &first).unwrap();
+        apng.finish().unwrap();
+
+        let chunks = chunks(&read_file(&path));
+        let (kinds, controls) = frames(&chunks);
+        assert_eq!(kinds, ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]);
+        assert_eq!(chunks[0].1, png::header(RECORD_WIDTH, RECORD_HEIGHT));
+        /* the frame count is filled in at the end */
+        assert_eq!(chunks[1].1, animation_control(2));
+        /* the unchanged frames lengthen the first, the second has only the rows that changed */
+        assert_eq!(controls, [(0, RECORD_HEIGHT as u32, 0, delay(0, 3)), (1, 10, 10, delay(3, 1))]);
+        assert_eq!(word(&chunks[5].1, 0), 2);
+        assert_eq!(chunks[5].1[4..],
+                   png::image_data(RECORD_WIDTH, 10, &second[10 * RECORD_PITCH..20 * RECORD_PITCH])
+                       [..]);
+    }
+
+    #[test]
+    fn apng_splits_long_delays() {
+        let path = temp_path("recorder-test-delay.png");
+        let mut apng = Apng::new(&path).unwrap();
+        let still = picture(0x33);
+        apng.frame(&still, &[]).unwrap();
+        /* a bit over a minute with nothing changing */
+        let seen = 4000;
+        for _ in 1..seen {
+            apng.frame(&still, &still).unwrap();
+        }
+        apng.finish().unwrap();
+
+        let chunks = chunks(&read_file(&path));
+        let (kinds, controls) = frames(&chunks);
+        assert_eq!(kinds, ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]);
+        assert_eq!(chunks[1].1, animation_control(2));
+        /* the second frame repeats a row so the first can end */
+        assert_eq!((controls[1].0, controls[1].1, controls[1].2), (1, 1, 0));
+        assert!(controls.iter().all(|control| control.3 <= MAX_DELAY));
+        assert!(controls[0].3 + delay(0, 1) > MAX_DELAY);
+        assert_eq!(controls[0].3 + controls[1].3, delay(0, seen));
+    }
+
+    #[test]
+    fn apng_without_frames_still_has_a_picture() {
+        let path = temp_path("recorder-test-empty.png");
+        Apng::new(&path).unwrap().finish().unwrap();
+
+        let chunks = chunks(&read_file(&path));
+        let (kinds, controls) = frames(&chunks);
+        assert_eq!(kinds, ["IHDR", "acTL", "fcTL", "IDAT", "IEND"]);
+        assert_eq!(chunks[1].1, animation_control(1));
+        assert_eq!(controls, [(0, RECORD_HEIGHT as u32, 0, delay(0, 1))]);
+    }
+
+    #[test]
+    fn wav_header_sizes() {
+        let header = wav_header(1000);
+        assert_eq!(header.len(), 44);
+        assert_eq!(header[..4], *b"RIFF");
+        assert_eq!(word_le(&header, 4), 1036);
+        assert_eq!(header[8..16], *b"WAVEfmt ");
+        assert_eq!(word_le(&header, 24), 44100);
+        assert_eq!(word_le(&header, 28), 88200);
+        assert_eq!(header[36..40], *b"data");
+        assert_eq!(word_le(&header, 40), 1000);
+    }
+
+    #[test]
+    fn wav_samples_follow_the_speaker() {
+        let path = temp_path("recorder-test.wav");
+        let mut wav = Wav::new(Path::new(&path)).unwrap();
+        /* nothing is heard before the first frame */
+        wav.speaker(500, true).unwrap();
+        assert_eq!(wav.samples, 0);
+
+        let start = 1000;
+        wav.start(start);
+        wav.start(start + 5000);
+        wav.speaker(start, false).unwrap();
+        /* sixty frames, a shade over a second at 59.92 a second */
+        let second = CYCLES_PER_FRAME * 60;
+        wav.speaker(start + second / 2, true).unwrap();
+        wav.speaker(start + second, false).unwrap();
+        let (samples, rate) = (wav.samples, wav.rate);
+        assert_eq!(samples, second * rate.0 / rate.1);
+        assert_eq!(samples, 44156);
+        wav.finish().unwrap();
+
+        let data = read_file(&path);
+        assert_eq!(data.len() as u64, 44 + samples * 2);
+        assert_eq!(word_le(&data, 4) as u64, 36 + samples * 2);
+        assert_eq!(word_le(&data, 40) as u64, samples * 2);
+        let sample = |index: usize| (data[44 + index * 2] as i16) | (data[45 + index * 2] as i16) << 8;
+        let half = (second / 2 * rate.0 / rate.1) as usize;
+        assert_eq!(sample(0), -SPEAKER_LEVEL);
+        assert_eq!(sample(half - 1), -SPEAKER_LEVEL);
+        assert_eq!(sample(half), SPEAKER_LEVEL);
+        assert_eq!(sample(samples as usize - 1), SPEAKER_LEVEL);
+    }
+}
@@ -0,0 +1,332 @@
+use model::Model;
+
+/* Telling roms apart.
+ *
+ * Known dumps are recognised by CRC-32. They are catalogued chip by
+ * chip, since that is how they were read and images put together from
+ * them differ in padding and order, so a rom is known when every chip's
+ * range hashes to the chip's CRC.
+ *
+ * Failing that, Apple documents a few identification bytes in the monitor that
+ * software is meant to check to find out what it is running on:
+ *
+ *   $FBB3  $38 II, $EA II+ (and the /// in emulation), $06 //e and //c
+ *   $FB1E  $AD II+, $8A /// in emulation
+ *   $FBC0  $EA //e, $E0 enhanced //e, $00 //c
+ *   $FBBF  $FF original //c rom
+ *
+ * Going by those bytes is only a guess, as patched roms and clones
+ * keep them. A rom whose $FBB3 fits a family but whose other bytes
+ * don't is reported as patched or a clone, and run as that family
+ * anyway. The CRC-32 of the whole image is reported so unknown roms
+ * can be told apart.
+ */
+
+struct KnownRom {
+    name: &'static str,
+    model: Model,
+    /* (address, length, CRC-32) of each chip */
+    chips: &'static [(u16, usize, u32)],
+}
+
+impl KnownRom {
+    fn matches(&self, rom: &[u8]) -> bool {
+        self.chips.iter().all(|&(addr, len, crc)| crc32_at(rom, addr, len) == Some(crc))
+    }
+}
+
+static KNOWN_ROMS: [KnownRom; 7] = [
+    KnownRom {
+        name: "Apple II (341-0001 to 341-0003 Integer BASIC, 341-0004 monitor)",
+        model: Model::AppleII,
+        chips: &[(0xE000, 0x800, 0xC0A4AD3B),
+                 (0xE800, 0x800, 0xA99C2CF6),
+                 (0xF000, 0x800, 0x62230D38),
+                 (0xF800, 0x800, 0x020A86D0)],
+    },
+    KnownRom {
+        name: "Apple II+ (341-0011 to 341-0015, 341-0020 autostart monitor)",
+        model: Model::AppleIIPlus,
+        chips: &[(0xD000, 0x800, 0x6F05F949),
+                 (0xD800, 0x800, 0x1F08087C),
+                 (0xE000, 0x800, 0x2B8D9A89),
+                 (0xE800, 0x800, 0x5719871A),
+                 (0xF000, 0x800, 0x9A04EECF),
+                 (0xF800, 0x800, 0x079589C4)],
+    },
+    KnownRom {
+        name: "Franklin ACE 100 (II+ clone)",
+        model: Model::AppleIIPlus,
+        chips: &[(0xD000, 0x3000, 0x9D5EC94F)],
+    },
+    KnownRom {
+        name: "Pravetz 82 (II+ clone)",
+        model: Model::AppleIIPlus,
+        chips: &[(0xD000, 0x3000, 0x8E8E8BAF)],
+    },
+    KnownRom {
+        name: "Apple //e (342-0135-B, 342-0134-A)",
+        model: Model::AppleIIe,
+        chips: &[(0xC000, 0x2000, 0xE248835E), (0xE000, 0x2000, 0xFC3D59D8)],
+    },
+    KnownRom {
+        name: "enhanced Apple //e (342-0304-A, 342-0303-A)",
+        model: Model::AppleIIeEnhanced,
+        chips: &[(0xC000, 0x2000, 0x443AA7C4), (0xE000, 0x2000, 0x95E10034)],
+    },
+    KnownRom {
+        name: "Apple //c (original rom, version 255)",
+        model: Model::AppleIIc,
+        chips: &[(0xC000, 0x4000, 0xF66F9C26)],
+    },
+];
+
+struct Signature {
+    name: &'static str,
+    model: Option<Model>,
+    bytes: &'static [(u16, u8)],
+}
+
+static SIGNATURES: [Signature; 6] = [
+    Signature {
+        name: "Apple II (Integer BASIC, original monitor)",
+        model: Some(Model::AppleII),
+        bytes: &[(0xFBB3, 0x38)],
+    },
+    Signature {
+        name: "Apple II+ (Applesoft, autostart monitor)",
+        model: Some(Model::AppleIIPlus),
+        bytes: &[(0xFBB3, 0xEA), (0xFB1E, 0xAD)],
+    },
+    Signature {
+        name: "Apple /// in Apple II emulation mode",
+        model: None,
+        bytes: &[(0xFBB3, 0xEA), (0xFB1E, 0x8A)],
+    },
+    Signature {
+        name: "Apple //e",
+        model: Some(Model::AppleIIe),
+        bytes: &[(0xFBB3, 0x06), (0xFBC0, 0xEA)],
+    },
+    Signature {
+        name: "enhanced Apple //e",
+        model: Some(Model::AppleIIeEnhanced),
+        bytes: &[(0xFBB3, 0x06), (0xFBC0, 0xE0)],
+    },
+    Signature {
+        name: "Apple //c",
+        model: Some(Model::AppleIIc),
+        bytes: &[(0xFBB3, 0x06), (0xFBC0, 0x00), (0xFBBF, 0xFF)],
+    },
+];
+
+pub enum RomMatch {
+    /* the CRC-32 of every chip matched a known dump */
+    Known(&'static str, Model),
+    /* no known dump, but every identification byte matched */
+    Signature(&'static str, Option<Model>),
+    /* the family byte matched but the rest didn't */
+    Patched(Model),
+    Unknown,
+}
+
+pub struct RomInfo {
+    pub crc32: u32,
+    pub found: RomMatch,
+}
+
+impl RomInfo {
+    /* The model to run the rom as, if it says. */
+    pub fn model(&self) -> Option<Model> {
+        match self.found {
+            RomMatch::Known(_, model) => Some(model),
+            RomMatch::Signature(_, model) => model,
+            RomMatch::Patched(model) => Some(model),
+            RomMatch::Unknown => None,
+        }
+    }
+}
+
+/* The byte of a rom image ending at $FFFF that sits at `addr`. */
+fn byte_at(rom: &[u8], addr: u16) -> Option<u8> {
+    let offset = addr as usize + rom.len();
+    if offset < 0x10000 {
+        None
+    } else {
+        Some(rom[offset - 0x10000])
+    }
+}
+
+/* The CRC-32 of `len` bytes of the rom from `addr`, if it covers them. */
+fn crc32_at(rom: &[u8], addr: u16, len: usize) -> Option<u32> {
+    let offset = addr as usize + rom.len();
+    if offset < 0x10000 || offset - 0x10000 + len > rom.len() {
+        None
+    } else {
+        Some(crc32(&rom[offset - 0x10000..offset - 0x10000 + len]))
+    }
+}
+
+fn family(rom: &[u8]) -> Option<Model> {
+    match byte_at(rom, 0xFBB3) {
+        Some(0x38) => Some(Model::AppleII),
+        Some(0xEA) => Some(Model::AppleIIPlus),
+        Some(0x06) => Some(Model::AppleIIe),
+        _ => None,
+    }
+}
+
+pub fn identify(rom: &[u8]) -> RomInfo {
+    let known = KNOWN_ROMS.iter()
+        .find(|known| known.matches(rom))
+        .map(|known| RomMatch::Known(known.name, known.model));
+    let signature = SIGNATURES.iter()
+        .find(|sig| sig.bytes.iter().all(|&(addr, val)| byte_at(rom, addr) == Some(val)))
+        .map(|sig| RomMatch::Signature(sig.name, sig.model));
+
+    RomInfo {
+        crc32: crc32(rom),
+        found: match (known, signature, family(rom)) {
+            (Some(known), _, _) => known,
+            (None, Some(signature), _) => signature,
+            (None, None, Some(model)) => RomMatch::Patched(model),
+            (None, None, None) => RomMatch::Unknown,
+        },
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn chip_ranges_count_from_the_end() {
+        let rom: Vec<u8> = (0..0x3000).map(|i| (i >> 8) as u8).collect();
+        assert_eq!(crc32_at(&rom, 0xD000, 0x800), Some(crc32(&rom[..0x800])));
+        assert_eq!(crc32_at(&rom, 0xF800, 0x800), Some(crc32(&rom[0x2800..])));
+        assert_eq!(crc32_at(&rom, 0xC000, 0x2000), None);
+    }
+
+    #[test]
+    fn identification_bytes_are_the_fallback() {
+        let mut rom = vec![0; 0x4000];
+        rom[0x3BB3] = 0x06;
+        rom[0x3BC0] = 0xE0;
+        match identify(&rom).found {
+            RomMatch::Signature(_, Some(Model::AppleIIeEnhanced)) => {}
+            _ => panic!("expected the enhanced //e signature"),
+        }
+
+        rom[0x3BC0] = 0x12;
+        match identify(&rom).found {
+            RomMatch::Patched(Model::AppleIIe) => {}
+            _ => panic!("expected a patched //e"),
+        }
+
+        rom[0x3BB3] = 0x12;
+        match identify(&rom).found {
+            RomMatch::Unknown => {}
+            _ => panic!("expected an unknown rom"),
+        }
+
+        let mut rom = vec![0; 0x3000];
+        rom[0x2BB3] = 0x38;
+        match identify(&rom).found {
+            RomMatch::Signature(_, Some(Model::AppleII)) => {}
+            _ => panic!("expected the II signature"),
+        }
+
+        rom[0x2BB3] = 0xEA;
+        rom[0x2B1E] = 0xAD;
+        match identify(&rom).found {
+            RomMatch::Signature(_, Some(Model::AppleIIPlus)) => {}
+            _ => panic!("expected the II+ signature"),
+        }
+
+        /* a clone that kept the family byte but not the rest */
+        rom[0x2B1E] = 0x12;
+        match identify(&rom).found {
+            RomMatch::Patched(Model::AppleIIPlus) => {}
+            _ => panic!("expected a patched II+"),
+        }
+    }
+
+    #[test]
+    fn known_roms_fit_their_models() {
+        for model in &[Model::AppleII,
+                       Model::AppleIIPlus,
+                       Model::AppleIIe,
+                       Model::AppleIIeEnhanced,
+                       Model::AppleIIc] {
+            assert!(KNOWN_ROMS.iter().any(|known| known.model == *model),
+                    "no known rom for the {}",
+                    model.name());
+        }
+        assert!(KNOWN_ROMS.iter().filter(|known| known.name.contains("clone")).count() >= 2);
+
+        for known in KNOWN_ROMS.iter() {
+            /* chips run in order up to $FFFF, inside the model's rom */
+            let mut next = 0x10000 - known.model.rom_size();
+            let first = known.chips[0].0 as usize;
+            assert!(first >= next, "{} starts below the rom", known.name);
+            next = first;
+            for &(addr, len, _) in known.chips {
+                assert_eq!(addr as usize, next, "{} has a gap", known.name);
+                next += len;
+            }
+            assert_eq!(next, 0x10000, "{} does not end at $FFFF", known.name);
+        }
+    }
+
+    #[test]
+    fn chip_crcs_come_before_identification_bytes() {
+        let mut rom: Vec<u8> = (0..0x3000).map(|i| (i * 7) as u8).collect();
+        rom[0x2BB3] = 0xEA;
+        rom[0x2B1E] = 0xAD;
+        let chips: Vec<(u16, usize, u32)> = (0..6)
+            .map(|chip| {
+                let addr = 0xD000 + chip * 0x800;
+                (addr as u16, 0x800, crc32_at(&rom, addr as u16, 0x800).unwrap())
+            })
+            .collect();
+        let known = KnownRom {
+            name: "test",
+            model: Model::AppleIIPlus,
+            chips: Box::leak(chips.into_boxed_slice()),
+        };
+        assert!(known.matches(&rom));
+
+        /* a padded image still matches, counting from the end */
+        let mut padded = vec![0xFF; 0x1000];
+        padded.extend_from_slice(&rom);
+        assert!(known.matches(&padded));
+
+        /* a patched monitor keeps its identification bytes */
+        rom[0x2F00] ^= 0xFF;
+        assert!(!known.matches(&rom));
+        match identify(&rom).found {
+            RomMatch::Signature(_, Some(Model::AppleIIPlus)) => {}
+            _ => panic!("expected only the II+ signature"),
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use mapper::{Mapper, ScreenState};
+
+/* Timing of the NTSC video scanner, in cpu cycles.
+ *
+ * Every line is 65 cycles, the first 25 of which are horizontal
+ * blanking, and a frame is 262 lines of which the first 192 are shown.
+ */
+
+pub const CYCLES_PER_LINE: u64 = 65;
+pub const LINES_PER_FRAME: u64 = 262;
+pub const CYCLES_PER_FRAME: u64 = CYCLES_PER_LINE * LINES_PER_FRAME;
+pub const VISIBLE_LINES: u64 = 192;
+pub const HBLANK_CYCLES: u64 = 25;
+/* Frames a second as a fraction: the 14.31818 MHz master clock over
+ * 912 dots a line and 262 lines, a little under 60.
+ */
+pub const FRAME_RATE: (u64, u64) = (1640625, 27379);
+/* Flashing text swaps between normal and inverse every 16 frames. */
+pub const FLASH_FRAMES: u64 = 16;
+
+pub fn in_vblank(cycles: u64) -> bool {
+    (cycles % CYCLES_PER_FRAME) / CYCLES_PER_LINE >= VISIBLE_LINES
+}
+
+pub fn frame(cycles: u64) -> u64 {
+    cycles / CYCLES_PER_FRAME
+}
+
+/* Whether flashing characters are in their inverse half, which comes
+ * around a little under twice a second.
+ */
+pub fn flash_inverse(cycles: u64) -> bool {
+    (frame(cycles) / FLASH_FRAMES) % 2 == 1
+}
+
+/* The address the video circuitry is fetching on the given cycle,
+ * following the counter and adder equations of the II+ video
+ * generator (Sather, Understanding the Apple II, ch. 3 and 5).
+ *
+ * The horizontal counter goes $00, $40-$7F and the vertical counter
+ * goes $100-$1FF then $0FA-$0FF, with the frame starting at the top
+ * visible line.
+ */
+pub fn address(cycles: u64, screen: &ScreenState) -> u16 {
+    let frame_cycle = cycles % CYCLES_PER_FRAME;
+    let line = frame_cycle / CYCLES_PER_LINE;
+    let line_cycle = frame_cycle % CYCLES_PER_LINE;
+
+    let h = if line_cycle == 0 { 0 } else { 0x3F + line_cycle } as u16;
+    let v = if line < 0x100 { 0x100 + line } else { 0xFA + line - 0x100 } as u16;
+
+    let bit = |val: u16, n: u16| (val >> n) & 1;
+    let (h3, h4, h5) = (bit(h, 3), bit(h, 4), bit(h, 5));
+    let (v2, v3, v4) = (bit(v, 5), bit(v, 6), bit(v, 7));
+
+    let sum = (0xD + ((h5 << 2) | (h4 << 1) | h3) + ((v4 << 3) | (v3 << 2) | (v4 << 1) | v3)) & 0xF;
+    let mut addr = (h & 0x7) | (sum << 3) | (((v >> 3) & 0x7) << 7);
+
+    /* the bottom four text lines of mixed mode are fetched as text */
+    let high_res = screen.graphics && !screen.low_res && !(!screen.all && v4 != 0 && v2 != 0);
+    if high_res {
+        addr |= (v & 0x7) << 10;
+        addr |= screen.hires_page() as u16;
+    } else {
+        addr |= screen.text_page() as u16;
+        /* during horizontal blanking the II+ adds $1000 */
+        if h5 == 0 && (h4 == 0 || h3 == 0) {
+            addr |= 0x1000;
+        }
+    }
+
+    addr
+}
+
+/* The line and 7-dot column the beam is drawing on the given cycle,
+ * or None while it is blanked.
+ */
+pub fn position(cycles: u64) -> Option<(usize, usize)> {
+    let frame_cycle = cycles % CYCLES_PER_FRAME;
+    let line = frame_cycle / CYCLES_PER_LINE;
+    let line_cycle = frame_cycle % CYCLES_PER_LINE;
+    if line >= VISIBLE_LINES || line_cycle < HBLANK_CYCLES {
+        None
+    } else {
+        Some((line as usize, (line_cycle - HBLANK_CYCLES) as usize))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FetchKind {
+    Text,
+    Text80,
+    LowRes,
+    HighRes,
+}
+
+/* What the video circuitry fetched for one column, along with the
+ * switches that decide how it is shown.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub struct Fetch {
+    pub kind: FetchKind,
+    pub main: u8,
+    pub aux: u8,
+    pub altcharset: bool,
+}
+
+impl Fetch {
+    pub fn new() -> Fetch {
+        Fetch {
+            kind: FetchKind::Text,
+            main: 0,
+            aux: 0,
+            altcharset: false,
+        }
+    }
+}
+
+/* Latches the byte under the beam on the given cycle the way the
+ * video circuitry sees it right then, with the display switches as
+ * `screen` has them.
+ */
+pub fn fetch(cycles: u64, memory: &Mapper, screen: &ScreenState) -> Fetch {
+    let line = (cycles % CYCLES_PER_FRAME) / CYCLES_PER_LINE;
+    let mixed_text = !screen.all && line >= VISIBLE_LINES - 32;
+
+    let kind = if screen.graphics && !mixed_text {
+        if screen.low_res {
+            FetchKind::LowRes
+        } else {
+            FetchKind::HighRes
+        }
+    } else if screen.col80 {
+        FetchKind::Text80
+    } else {
+        FetchKind::Text
+    };
+
+    let addr = address(cycles, screen) as usize;
+    Fetch {
+        kind: kind,
+        main: memory.ram[addr],
+        aux: memory.aux_ram.get(addr).cloned().unwrap_or(0),
+        altcharset: screen.altcharset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{Mapper, ScreenState};
+    use model::Model;
+    use ram_init::RamInit;
+
+    fn cycle(line: u64, line_cycle: u64) -> u64 {
+        line * CYCLES_PER_LINE + line_cycle
+    }
+
+    /* Scanned addresses from Sather, Understanding the Apple II, table
+     * 5.1 and figure 5.8, with the text page 1 and hires page 1 bases.
+     */
+    #[test]
+    fn address_at_known_positions() {
+        let text = ScreenState::new();
+        let mut hires = ScreenState::new();
+        hires.graphics = true;
+        hires.low_res = false;
+
+        /* horizontal blanking on line 0, with the II+'s $1000 added */
+        assert_eq!(address(cycle(0, 0), &text), 0x1468);
+        assert_eq!(address(cycle(0, 1), &text), 0x1468);
+        assert_eq!(address(cycle(0, HBLANK_CYCLES - 1), &text), 0x147F);
+
+        /* first and last visible bytes */
+        assert_eq!(address(cycle(0, HBLANK_CYCLES), &text), 0x0400);
+        assert_eq!(address(cycle(0, CYCLES_PER_LINE - 1), &text), 0x0427);
+        assert_eq!(address(cycle(63, HBLANK_CYCLES), &text), 0x0780);
+        assert_eq!(address(cycle(64, HBLANK_CYCLES), &text), 0x0428);
+        assert_eq!(address(cycle(191, HBLANK_CYCLES), &text), 0x07D0);
+
+        assert_eq!(address(cycle(0, HBLANK_CYCLES), &hires), 0x2000);
+        assert_eq!(address(cycle(1, HBLANK_CYCLES), &hires), 0x2400);
+        assert_eq!(address(cycle(63, HBLANK_CYCLES), &hires), 0x3F80);
+        assert_eq!(address(cycle(64, HBLANK_CYCLES), &hires), 0x2028);
+        assert_eq!(address(cycle(191, HBLANK_CYCLES), &hires), 0x3FD0);
+
+        /* vertical blanking scans the screen holes */
+        assert_eq!(address(cycle(192, HBLANK_CYCLES), &text), 0x0478);
+        assert!(in_vblank(cycle(192, 0)));
+        assert!(in_vblank(cycle(LINES_PER_FRAME - 1, CYCLES_PER_LINE - 1)));
+        assert!(!in_vblank(cycle(191, CYCLES_PER_LINE - 1)));
+
+        /* the frame wraps after 17030 cycles */
+        assert_eq!(CYCLES_PER_FRAME, 17030);
+        assert_eq!(address(CYCLES_PER_FRAME, &text), 0x1468);
+        assert_eq!(address(CYCLES_PER_FRAME + HBLANK_CYCLES, &text), 0x0400);
+        assert!(!in_vblank(CYCLES_PER_FRAME));
+    }
+
+    /* Every TEXT, MIXED, PAGE2 and HIRES setting, with 80STORE off and on. */
+    fn screens() -> Vec<ScreenState> {
+        (0..32)
+            .map(|bits| {
+                let mut screen = ScreenState::new();
+                screen.graphics = bits & 0x01 != 0;
+                screen.all = bits & 0x02 == 0;
+                screen.primary = bits & 0x04 == 0;
+                screen.low_res = bits & 0x08 == 0;
+                screen.store80 = bits & 0x10 != 0;
+                screen
+            })
+            .collect()
+    }
+
+    fn describe(screen: &ScreenState) -> String {
+        format!("graphics {} all {} primary {} low_res {} store80 {}",
+                screen.graphics,
+                screen.all,
+                screen.primary,
+                screen.low_res,
+                screen.store80)
+    }
+
+    #[test]
+    fn display_pages() {
+        for screen in screens() {
+            /* with 80STORE on PAGE2 picks aux memory, not page 2 */
+            let page2 = !screen.primary && !screen.store80;
+            let name = describe(&screen);
+            assert_eq!(screen.text_page(), if page2 { 0x800 } else { 0x400 }, "{}", name);
+            assert_eq!(screen.hires_page(), if page2 { 0x4000 } else { 0x2000 }, "{}", name);
+        }
+    }
+
+    /* Line 0 shows graphics when they are on. Line 170 is in the bottom
+     * four text rows, so mixed mode shows text there.
+     */
+    #[test]
+    fn address_for_each_mode() {
+        for screen in screens() {
+            let page2 = !screen.primary && !screen.store80;
+            let text = if page2 { 0x800 } else { 0x400 };
+            let hires = if page2 { 0x4000 } else { 0x2000 };
+            let hires_shown = screen.graphics && !screen.low_res;
+            let name = describe(&screen);
+
+            let top = if hires_shown { hires } else { text };
+            assert_eq!(address(cycle(0, HBLANK_CYCLES), &screen), top, "line 0, {}", name);
+
+            let bottom = if hires_shown && screen.all { hires + 0xAD0 } else { text + 0x2D0 };
+            assert_eq!(address(cycle(170, HBLANK_CYCLES), &screen), bottom, "line 170, {}", name);
+        }
+    }
+
+    #[test]
+    fn fetch_for_each_mode() {
+        let mut memory = Mapper::new(Model::AppleIIe, vec![0; 0x4000], 0xC000, RamInit::Zero);
+        /* mark each page so the fetched byte says where it came from */
+        for addr in 0x400..0x800 {
+            memory.ram[addr] = 1;
+            memory.ram[addr + 0x400] = 2;
+            memory.aux_ram[addr] = 5;
+        }
+        for addr in 0x2000..0x4000 {
+            memory.ram[addr] = 3;
+            memory.ram[addr + 0x2000] = 4;
+        }
+
+        for screen in screens() {
+            let page2 = !screen.primary && !screen.store80;
+            let graphics_kind = if screen.low_res { FetchKind::LowRes } else { FetchKind::HighRes };
+            let graphics_byte = match (screen.low_res, page2) {
+                (true, false) => 1,
+                (true, true) => 2,
+                (false, false) => 3,
+                (false, true) => 4,
+            };
+            let text_byte = if page2 { 2 } else { 1 };
+            let name = describe(&screen);
+            memory.screen = screen;
+
+            let top = fetch(cycle(0, HBLANK_CYCLES), &memory, &memory.screen);
+            let bottom = fetch(cycle(170, HBLANK_CYCLES), &memory, &memory.screen);
+            if memory.screen.graphics {
+                assert!(top.kind == graphics_kind, "line 0, {}", name);
+                assert_eq!(top.main, graphics_byte, "line 0, {}", name);
+            } else {
+                assert!(top.kind == FetchKind::Text, "line 0, {}", name);
+                assert_eq!(top.main, text_byte, "line 0, {}", name);
+            }
+            if memory.screen.graphics && memory.screen.all {
+                assert!(bottom.kind == graphics_kind, "line 170, {}", name);
+                assert_eq!(bottom.main, graphics_byte, "line 170, {}", name);
+            } else {
+                assert!(bottom.kind == FetchKind::Text, "line 170, {}", name);
+                assert_eq!(bottom.main, text_byte, "line 170, {}", name);
+            }
+            /* aux is latched from the same address, page 1 text here */
+            if top.main == 1 {
+                assert_eq!(top.aux, 5, "line 0, {}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn flash_toggles_every_flash_frames() {
+        let mut inverse = flash_inverse(0);
+        assert!(!inverse);
+        for frame in 1..FLASH_FRAMES * 4 {
+            let now = flash_inverse(frame * CYCLES_PER_FRAME);
+            assert_eq!(now != inverse, frame % FLASH_FRAMES == 0, "frame {}", frame);
+            assert_eq!(flash_inverse((frame + 1) * CYCLES_PER_FRAME - 1), now, "end of frame {}", frame);
+            inverse = now;
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use super::{Frame, VideoOutput};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/* The last frame a headless output was given. */
+pub struct Shown {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    /* how many frames have been shown */
+    pub frames: u64,
+}
+
+impl Shown {
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let offset = (y * self.width + x) * 4;
+        (self.pixels[offset], self.pixels[offset + 1], self.pixels[offset + 2])
+    }
+}
+
+/* Keeps frames in memory instead of showing them, for running without
+ * a display. What it has been shown can still be looked at through
+ * shown() once it belongs to a monitor.
+ */
+pub struct Headless {
+    shown: Rc<RefCell<Shown>>,
+}
+
+impl Headless {
+    pub fn new() -> Headless {
+        Headless {
+            shown: Rc::new(RefCell::new(Shown {
+                width: 0,
+                height: 0,
+                pixels: Vec::new(),
+                frames: 0,
+            })),
+        }
+    }
+
+    pub fn shown(&self) -> Rc<RefCell<Shown>> {
+        self.shown.clone()
+    }
+}
+
+impl VideoOutput for Headless {
+    fn show(&mut self, frame: &Frame) {
+        let mut shown = self.shown.borrow_mut();
+        if shown.width != frame.width || shown.height != frame.height {
+            shown.width = frame.width;
+            shown.height = frame.height;
+            shown.pixels = frame.pixels.to_vec();
+        } else if let Some((first, last)) = frame.changed {
+            let pitch = frame.pitch();
+            let rows = first * pitch..(last + 1) * pitch;
+            shown.pixels[rows.clone()].copy_from_slice(&frame.pixels[rows]);
+        }
+        shown.frames += 1;
+    }
+}
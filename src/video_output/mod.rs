@@ -0,0 +1,33 @@
+pub mod sdl;
+pub mod headless;
+
+pub use self::sdl::SdlOutput;
+pub use self::headless::Headless;
+
+/* A finished picture, RGBA pixels one row after another. */
+pub struct Frame<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: &'a [u8],
+    /* The first and last rows that differ from the frame shown before
+     * this one, or None if none do. Outputs that hold on to the last
+     * frame only need to copy these.
+     */
+    pub changed: Option<(usize, usize)>,
+}
+
+impl<'a> Frame<'a> {
+    pub fn pitch(&self) -> usize {
+        self.width * 4
+    }
+}
+
+/* Where the monitor sends each frame once the beam has drawn it. */
+pub trait VideoOutput {
+    fn show(&mut self, frame: &Frame);
+
+    /* How big frames end up on screen, for outputs that have one. */
+    fn window_size(&self) -> Option<(usize, usize)> {
+        None
+    }
+}
@@ -0,0 +1,83 @@
+use super::{Frame, VideoOutput};
+
+use sdl2::VideoSubsystem;
+use sdl2::render::{Renderer, Texture};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+
+/* Frames are RGBA bytes. SDL names formats by the packed word, so
+ * which one that is depends on byte order.
+ */
+#[cfg(target_endian = "little")]
+const FRAME_FORMAT: PixelFormatEnum = PixelFormatEnum::ABGR8888;
+#[cfg(target_endian = "big")]
+const FRAME_FORMAT: PixelFormatEnum = PixelFormatEnum::RGBA8888;
+
+/* Shows frames in a window, scaled to fit it. */
+pub struct SdlOutput<'a> {
+    renderer: Renderer<'a>,
+    /* a streaming texture the size of the last frame */
+    texture: Option<(Texture, usize, usize)>,
+}
+
+impl<'a> SdlOutput<'a> {
+    /* Opens a window for frames of the given size, drawn twice as tall
+     * since the Apple's are twice as wide as they should look.
+     */
+    pub fn new(sdl_video: VideoSubsystem, width: usize, height: usize) -> SdlOutput<'a> {
+        let mut window = sdl_video.window("APPLE ][", width as u32, height as u32 * 2)
+            .position_centered()
+            .build()
+            .expect("Could not make window.");
+        window.set_minimum_size(width as u32 / 2, height as u32)
+            .expect("Could not set min size.");
+
+        let renderer = window.renderer()
+            .accelerated()
+            .build()
+            .expect("Could not make renderer");
+
+        SdlOutput {
+            renderer: renderer,
+            texture: None,
+        }
+    }
+}
+
+impl<'a> VideoOutput for SdlOutput<'a> {
+    fn show(&mut self, frame: &Frame) {
+        let reuse = match self.texture {
+            Some((_, width, height)) => width == frame.width && height == frame.height,
+            None => false,
+        };
+        let changed = if reuse {
+            frame.changed
+        } else {
+            let texture = self.renderer
+                .create_texture_streaming(FRAME_FORMAT, frame.width as u32, frame.height as u32)
+                .expect("Could not create texture.");
+            self.texture = Some((texture, frame.width, frame.height));
+            /* a new texture has nothing in it yet */
+            Some((0, frame.height - 1))
+        };
+
+        let texture = &mut self.texture.as_mut().unwrap().0;
+        if let Some((first, last)) = changed {
+            let pitch = frame.pitch();
+            let rows = Rect::new(0, first as i32, frame.width as u32, (last + 1 - first) as u32);
+            texture.update(Some(rows), &frame.pixels[first * pitch..(last + 1) * pitch], pitch)
+                .expect("Could not update texture.");
+        }
+
+        self.renderer.clear();
+        self.renderer
+            .copy(texture, None, None)
+            .expect("Could not copy texture.");
+        self.renderer.present();
+    }
+
+    fn window_size(&self) -> Option<(usize, usize)> {
+        let (width, height) = self.renderer.window().unwrap().size();
+        Some((width as usize, height as usize))
+    }
+}
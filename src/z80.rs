@@ -0,0 +1,1344 @@
+use r6502::memory::Memory;
+
+/* A Zilog Z-80 core.
+ *
+ * Decoding follows the x/y/z/p/q split of the opcode byte:
+ *   x = bits 7-6, y = bits 5-3, z = bits 2-0, p = y >> 1, q = y & 1
+ * which keeps the DD/FD index prefixes down to a substitution of
+ * HL with IX/IY rather than separate opcode tables.
+ *
+ * There is nothing behind the I/O ports; IN reads $FF and OUT is dropped.
+ */
+
+const FLAG_C: u8 = 0x01;
+const FLAG_N: u8 = 0x02;
+const FLAG_PV: u8 = 0x04;
+const FLAG_X: u8 = 0x08;
+const FLAG_H: u8 = 0x10;
+const FLAG_Y: u8 = 0x20;
+const FLAG_Z: u8 = 0x40;
+const FLAG_S: u8 = 0x80;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Index {
+    HL,
+    IX,
+    IY,
+}
+
+pub struct Z80 {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    /* AF', BC', DE', HL' */
+    alt: [u16; 4],
+    pub ix: u16,
+    pub iy: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub i: u8,
+    pub r: u8,
+    iff1: bool,
+    iff2: bool,
+    im: u8,
+    halted: bool,
+}
+
+impl Z80 {
+    pub fn new() -> Z80 {
+        Z80 {
+            a: 0xFF,
+            f: 0xFF,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            alt: [0; 4],
+            ix: 0,
+            iy: 0,
+            sp: 0xFFFF,
+            pc: 0,
+            i: 0,
+            r: 0,
+            iff1: false,
+            iff2: false,
+            im: 0,
+            halted: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.i = 0;
+        self.r = 0;
+        self.iff1 = false;
+        self.iff2 = false;
+        self.im = 0;
+        self.halted = false;
+    }
+
+    /* Runs one instruction and returns the T-states it took. */
+    pub fn step<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M) -> u32 {
+        if self.halted {
+            self.inc_r();
+            return 4;
+        }
+
+        let mut idx = Index::HL;
+        let mut cycles = 0;
+        loop {
+            let op = self.fetch(mem);
+            self.inc_r();
+            match op {
+                0xDD => {
+                    idx = Index::IX;
+                    cycles += 4;
+                }
+                0xFD => {
+                    idx = Index::IY;
+                    cycles += 4;
+                }
+                0xCB => {
+                    let taken = if idx == Index::HL {
+                        self.inc_r();
+                        self.exec_cb(mem)
+                    } else {
+                        self.exec_index_cb(mem, idx)
+                    };
+                    return cycles + taken;
+                }
+                0xED => {
+                    self.inc_r();
+                    return cycles + self.exec_ed(mem);
+                }
+                _ => return cycles + self.exec(mem, op, idx),
+            }
+        }
+    }
+
+    fn inc_r(&mut self) {
+        self.r = (self.r & 0x80) | (self.r.wrapping_add(1) & 0x7F);
+    }
+
+    fn fetch<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M) -> u8 {
+        let val = mem.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        val
+    }
+
+    fn fetch16<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M) -> u16 {
+        let lo = self.fetch(mem) as u16;
+        let hi = self.fetch(mem) as u16;
+        (hi << 8) | lo
+    }
+
+    fn read16<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M, addr: u16) -> u16 {
+        let lo = mem.read(addr) as u16;
+        let hi = mem.read(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    fn write16<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M, addr: u16, val: u16) {
+        mem.write(addr, val as u8);
+        mem.write(addr.wrapping_add(1), (val >> 8) as u8);
+    }
+
+    fn push<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M, val: u16) {
+        self.sp = self.sp.wrapping_sub(2);
+        let sp = self.sp;
+        self.write16(mem, sp, val);
+    }
+
+    fn pop<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M) -> u16 {
+        let sp = self.sp;
+        let val = self.read16(mem, sp);
+        self.sp = self.sp.wrapping_add(2);
+        val
+    }
+
+    /* Register pairs */
+
+    fn bc(&self) -> u16 {
+        ((self.b as u16) << 8) | self.c as u16
+    }
+
+    fn de(&self) -> u16 {
+        ((self.d as u16) << 8) | self.e as u16
+    }
+
+    fn hl(&self) -> u16 {
+        ((self.h as u16) << 8) | self.l as u16
+    }
+
+    fn af(&self) -> u16 {
+        ((self.a as u16) << 8) | self.f as u16
+    }
+
+    fn set_bc(&mut self, val: u16) {
+        self.b = (val >> 8) as u8;
+        self.c = val as u8;
+    }
+
+    fn set_de(&mut self, val: u16) {
+        self.d = (val >> 8) as u8;
+        self.e = val as u8;
+    }
+
+    fn set_hl(&mut self, val: u16) {
+        self.h = (val >> 8) as u8;
+        self.l = val as u8;
+    }
+
+    fn set_af(&mut self, val: u16) {
+        self.a = (val >> 8) as u8;
+        self.f = val as u8;
+    }
+
+    /* HL, IX or IY depending on the prefix */
+    fn index(&self, idx: Index) -> u16 {
+        match idx {
+            Index::HL => self.hl(),
+            Index::IX => self.ix,
+            Index::IY => self.iy,
+        }
+    }
+
+    fn set_index(&mut self, idx: Index, val: u16) {
+        match idx {
+            Index::HL => self.set_hl(val),
+            Index::IX => self.ix = val,
+            Index::IY => self.iy = val,
+        }
+    }
+
+    /* Address of the (HL) operand, or (IX+d)/(IY+d) with the
+     * displacement fetched from the instruction stream.
+     */
+    fn index_addr<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M, idx: Index) -> u16 {
+        match idx {
+            Index::HL => self.hl(),
+            _ => {
+                let disp = self.fetch(mem) as i8 as u16;
+                self.index(idx).wrapping_add(disp)
+            }
+        }
+    }
+
+    /* rp table: BC, DE, HL, SP */
+    fn rp(&self, p: u8, idx: Index) -> u16 {
+        match p {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => self.index(idx),
+            _ => self.sp,
+        }
+    }
+
+    fn set_rp(&mut self, p: u8, idx: Index, val: u16) {
+        match p {
+            0 => self.set_bc(val),
+            1 => self.set_de(val),
+            2 => self.set_index(idx, val),
+            _ => self.sp = val,
+        }
+    }
+
+    /* rp2 table: BC, DE, HL, AF */
+    fn rp2(&self, p: u8, idx: Index) -> u16 {
+        match p {
+            3 => self.af(),
+            _ => self.rp(p, idx),
+        }
+    }
+
+    fn set_rp2(&mut self, p: u8, idx: Index, val: u16) {
+        match p {
+            3 => self.set_af(val),
+            _ => self.set_rp(p, idx, val),
+        }
+    }
+
+    /* r table: B, C, D, E, H, L, (HL), A. (HL) is handled by the caller. */
+    fn reg(&self, r: u8, idx: Index) -> u8 {
+        match r {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => (self.index(idx) >> 8) as u8,
+            5 => self.index(idx) as u8,
+            7 => self.a,
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_reg(&mut self, r: u8, idx: Index, val: u8) {
+        match r {
+            0 => self.b = val,
+            1 => self.c = val,
+            2 => self.d = val,
+            3 => self.e = val,
+            4 => {
+                let pair = (self.index(idx) & 0x00FF) | ((val as u16) << 8);
+                self.set_index(idx, pair);
+            }
+            5 => {
+                let pair = (self.index(idx) & 0xFF00) | val as u16;
+                self.set_index(idx, pair);
+            }
+            7 => self.a = val,
+            _ => unreachable!(),
+        }
+    }
+
+    fn cond(&self, y: u8) -> bool {
+        match y {
+            0 => self.f & FLAG_Z == 0,
+            1 => self.f & FLAG_Z != 0,
+            2 => self.f & FLAG_C == 0,
+            3 => self.f & FLAG_C != 0,
+            4 => self.f & FLAG_PV == 0,
+            5 => self.f & FLAG_PV != 0,
+            6 => self.f & FLAG_S == 0,
+            _ => self.f & FLAG_S != 0,
+        }
+    }
+
+    /* ALU */
+
+    fn szxy(val: u8) -> u8 {
+        let mut f = val & (FLAG_S | FLAG_X | FLAG_Y);
+        if val == 0 {
+            f |= FLAG_Z;
+        }
+        f
+    }
+
+    fn szxyp(val: u8) -> u8 {
+        let mut f = Z80::szxy(val);
+        if val.count_ones() % 2 == 0 {
+            f |= FLAG_PV;
+        }
+        f
+    }
+
+    fn add8(&mut self, val: u8, carry: bool) {
+        let c = if carry && self.f & FLAG_C != 0 { 1 } else { 0 };
+        let sum = self.a as u16 + val as u16 + c;
+        let res = sum as u8;
+        let mut f = Z80::szxy(res);
+        if (self.a ^ val ^ res) & 0x10 != 0 {
+            f |= FLAG_H;
+        }
+        if (self.a ^ res) & (val ^ res) & 0x80 != 0 {
+            f |= FLAG_PV;
+        }
+        if sum > 0xFF {
+            f |= FLAG_C;
+        }
+        self.a = res;
+        self.f = f;
+    }
+
+    fn sub8(&mut self, val: u8, carry: bool) -> u8 {
+        let c = if carry && self.f & FLAG_C != 0 { 1 } else { 0 };
+        let res = (self.a as u16).wrapping_sub(val as u16).wrapping_sub(c) as u8;
+        let mut f = Z80::szxy(res) | FLAG_N;
+        if (self.a ^ val ^ res) & 0x10 != 0 {
+            f |= FLAG_H;
+        }
+        if (self.a ^ val) & (self.a ^ res) & 0x80 != 0 {
+            f |= FLAG_PV;
+        }
+        if (self.a as u16) < val as u16 + c {
+            f |= FLAG_C;
+        }
+        self.f = f;
+        res
+    }
+
+    fn alu(&mut self, y: u8, val: u8) {
+        match y {
+            0 => self.add8(val, false),
+            1 => self.add8(val, true),
+            2 => self.a = self.sub8(val, false),
+            3 => self.a = self.sub8(val, true),
+            4 => {
+                self.a &= val;
+                self.f = Z80::szxyp(self.a) | FLAG_H;
+            }
+            5 => {
+                self.a ^= val;
+                self.f = Z80::szxyp(self.a);
+            }
+            6 => {
+                self.a |= val;
+                self.f = Z80::szxyp(self.a);
+            }
+            _ => {
+                /* CP takes the undocumented bits from the operand */
+                self.sub8(val, false);
+                self.f = (self.f & !(FLAG_X | FLAG_Y)) | (val & (FLAG_X | FLAG_Y));
+            }
+        }
+    }
+
+    fn inc8(&mut self, val: u8) -> u8 {
+        let res = val.wrapping_add(1);
+        let mut f = (self.f & FLAG_C) | Z80::szxy(res);
+        if val & 0x0F == 0x0F {
+            f |= FLAG_H;
+        }
+        if val == 0x7F {
+            f |= FLAG_PV;
+        }
+        self.f = f;
+        res
+    }
+
+    fn dec8(&mut self, val: u8) -> u8 {
+        let res = val.wrapping_sub(1);
+        let mut f = (self.f & FLAG_C) | Z80::szxy(res) | FLAG_N;
+        if val & 0x0F == 0x00 {
+            f |= FLAG_H;
+        }
+        if val == 0x80 {
+            f |= FLAG_PV;
+        }
+        self.f = f;
+        res
+    }
+
+    fn add16(&mut self, a: u16, b: u16) -> u16 {
+        let sum = a as u32 + b as u32;
+        let res = sum as u16;
+        let mut f = self.f & (FLAG_S | FLAG_Z | FLAG_PV);
+        f |= (res >> 8) as u8 & (FLAG_X | FLAG_Y);
+        if (a ^ b ^ res) & 0x1000 != 0 {
+            f |= FLAG_H;
+        }
+        if sum > 0xFFFF {
+            f |= FLAG_C;
+        }
+        self.f = f;
+        res
+    }
+
+    fn adc16(&mut self, a: u16, b: u16) -> u16 {
+        let c = (self.f & FLAG_C) as u32;
+        let sum = a as u32 + b as u32 + c;
+        let res = sum as u16;
+        let mut f = (res >> 8) as u8 & (FLAG_S | FLAG_X | FLAG_Y);
+        if res == 0 {
+            f |= FLAG_Z;
+        }
+        if (a ^ b ^ res) & 0x1000 != 0 {
+            f |= FLAG_H;
+        }
+        if (a ^ res) & (b ^ res) & 0x8000 != 0 {
+            f |= FLAG_PV;
+        }
+        if sum > 0xFFFF {
+            f |= FLAG_C;
+        }
+        self.f = f;
+        res
+    }
+
+    fn sbc16(&mut self, a: u16, b: u16) -> u16 {
+        let c = (self.f & FLAG_C) as u32;
+        let res = (a as u32).wrapping_sub(b as u32).wrapping_sub(c) as u16;
+        let mut f = ((res >> 8) as u8 & (FLAG_S | FLAG_X | FLAG_Y)) | FLAG_N;
+        if res == 0 {
+            f |= FLAG_Z;
+        }
+        if (a ^ b ^ res) & 0x1000 != 0 {
+            f |= FLAG_H;
+        }
+        if (a ^ b) & (a ^ res) & 0x8000 != 0 {
+            f |= FLAG_PV;
+        }
+        if (a as u32) < b as u32 + c {
+            f |= FLAG_C;
+        }
+        self.f = f;
+        res
+    }
+
+    /* CB rotates and shifts: RLC RRC RL RR SLA SRA SLL SRL */
+    fn rot(&mut self, y: u8, val: u8) -> u8 {
+        let carry_in = self.f & FLAG_C;
+        let (res, carry) = match y {
+            0 => (val.rotate_left(1), val & 0x80 != 0),
+            1 => (val.rotate_right(1), val & 0x01 != 0),
+            2 => ((val << 1) | carry_in, val & 0x80 != 0),
+            3 => ((val >> 1) | (carry_in << 7), val & 0x01 != 0),
+            4 => (val << 1, val & 0x80 != 0),
+            5 => ((val >> 1) | (val & 0x80), val & 0x01 != 0),
+            6 => ((val << 1) | 0x01, val & 0x80 != 0),
+            _ => (val >> 1, val & 0x01 != 0),
+        };
+        self.f = Z80::szxyp(res);
+        if carry {
+            self.f |= FLAG_C;
+        }
+        res
+    }
+
+    fn bit(&mut self, y: u8, val: u8) {
+        let res = val & (1 << y);
+        let mut f = (self.f & FLAG_C) | FLAG_H | (val & (FLAG_X | FLAG_Y));
+        if res == 0 {
+            f |= FLAG_Z | FLAG_PV;
+        }
+        if res & 0x80 != 0 {
+            f |= FLAG_S;
+        }
+        self.f = f;
+    }
+
+    fn daa(&mut self) {
+        let mut adjust = 0;
+        let mut carry = self.f & FLAG_C != 0;
+        if self.f & FLAG_H != 0 || self.a & 0x0F > 9 {
+            adjust |= 0x06;
+        }
+        if carry || self.a > 0x99 {
+            adjust |= 0x60;
+            carry = true;
+        }
+
+        let res = if self.f & FLAG_N != 0 {
+            self.a.wrapping_sub(adjust)
+        } else {
+            self.a.wrapping_add(adjust)
+        };
+
+        let mut f = Z80::szxyp(res) | (self.f & FLAG_N);
+        if (self.a ^ res) & 0x10 != 0 {
+            f |= FLAG_H;
+        }
+        if carry {
+            f |= FLAG_C;
+        }
+        self.a = res;
+        self.f = f;
+    }
+
+    /* Unprefixed opcodes, with HL possibly replaced by IX/IY. */
+    fn exec<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M, op: u8, idx: Index) -> u32 {
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        /* extra time for (IX+d) operands */
+        let disp_cycles = if idx == Index::HL { 0 } else { 8 };
+
+        match x {
+            0 => {
+                match z {
+                    0 => {
+                        match y {
+                            0 => 4,
+                            1 => {
+                                let af = self.af();
+                                let alt_af = self.alt[0];
+                                self.set_af(alt_af);
+                                self.alt[0] = af;
+                                4
+                            }
+                            2 => {
+                                let disp = self.fetch(mem) as i8 as u16;
+                                self.b = self.b.wrapping_sub(1);
+                                if self.b != 0 {
+                                    self.pc = self.pc.wrapping_add(disp);
+                                    13
+                                } else {
+                                    8
+                                }
+                            }
+                            3 => {
+                                let disp = self.fetch(mem) as i8 as u16;
+                                self.pc = self.pc.wrapping_add(disp);
+                                12
+                            }
+                            _ => {
+                                let disp = self.fetch(mem) as i8 as u16;
+                                if self.cond(y - 4) {
+                                    self.pc = self.pc.wrapping_add(disp);
+                                    12
+                                } else {
+                                    7
+                                }
+                            }
+                        }
+                    }
+                    1 => {
+                        if q == 0 {
+                            let val = self.fetch16(mem);
+                            self.set_rp(p, idx, val);
+                            10
+                        } else {
+                            let a = self.index(idx);
+                            let b = self.rp(p, idx);
+                            let res = self.add16(a, b);
+                            self.set_index(idx, res);
+                            11
+                        }
+                    }
+                    2 => {
+                        match (q, p) {
+                            (0, 0) => {
+                                mem.write(self.bc(), self.a);
+                                7
+                            }
+                            (0, 1) => {
+                                mem.write(self.de(), self.a);
+                                7
+                            }
+                            (0, 2) => {
+                                let addr = self.fetch16(mem);
+                                let val = self.index(idx);
+                                self.write16(mem, addr, val);
+                                16
+                            }
+                            (0, _) => {
+                                let addr = self.fetch16(mem);
+                                mem.write(addr, self.a);
+                                13
+                            }
+                            (_, 0) => {
+                                self.a = mem.read(self.bc());
+                                7
+                            }
+                            (_, 1) => {
+                                self.a = mem.read(self.de());
+                                7
+                            }
+                            (_, 2) => {
+                                let addr = self.fetch16(mem);
+                                let val = self.read16(mem, addr);
+                                self.set_index(idx, val);
+                                16
+                            }
+                            (_, _) => {
+                                let addr = self.fetch16(mem);
+                                self.a = mem.read(addr);
+                                13
+                            }
+                        }
+                    }
+                    3 => {
+                        let val = self.rp(p, idx);
+                        if q == 0 {
+                            self.set_rp(p, idx, val.wrapping_add(1));
+                        } else {
+                            self.set_rp(p, idx, val.wrapping_sub(1));
+                        }
+                        6
+                    }
+                    4 | 5 => {
+                        if y == 6 {
+                            let addr = self.index_addr(mem, idx);
+                            let val = mem.read(addr);
+                            let res = if z == 4 { self.inc8(val) } else { self.dec8(val) };
+                            mem.write(addr, res);
+                            11 + disp_cycles
+                        } else {
+                            let val = self.reg(y, idx);
+                            let res = if z == 4 { self.inc8(val) } else { self.dec8(val) };
+                            self.set_reg(y, idx, res);
+                            4
+                        }
+                    }
+                    6 => {
+                        if y == 6 {
+                            let addr = self.index_addr(mem, idx);
+                            let val = self.fetch(mem);
+                            mem.write(addr, val);
+                            10 + disp_cycles
+                        } else {
+                            let val = self.fetch(mem);
+                            self.set_reg(y, idx, val);
+                            7
+                        }
+                    }
+                    _ => {
+                        match y {
+                            0 | 1 | 2 | 3 => {
+                                let carry_in = self.f & FLAG_C;
+                                let (res, carry) = match y {
+                                    0 => (self.a.rotate_left(1), self.a & 0x80 != 0),
+                                    1 => (self.a.rotate_right(1), self.a & 0x01 != 0),
+                                    2 => ((self.a << 1) | carry_in, self.a & 0x80 != 0),
+                                    _ => ((self.a >> 1) | (carry_in << 7), self.a & 0x01 != 0),
+                                };
+                                self.a = res;
+                                self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_PV)) |
+                                         (res & (FLAG_X | FLAG_Y));
+                                if carry {
+                                    self.f |= FLAG_C;
+                                }
+                            }
+                            4 => self.daa(),
+                            5 => {
+                                self.a = !self.a;
+                                self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_PV | FLAG_C)) |
+                                         (self.a & (FLAG_X | FLAG_Y)) |
+                                         FLAG_H | FLAG_N;
+                            }
+                            6 => {
+                                self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_PV)) |
+                                         (self.a & (FLAG_X | FLAG_Y)) |
+                                         FLAG_C;
+                            }
+                            _ => {
+                                let h = if self.f & FLAG_C != 0 { FLAG_H } else { 0 };
+                                self.f = ((self.f & (FLAG_S | FLAG_Z | FLAG_PV | FLAG_C)) |
+                                          (self.a & (FLAG_X | FLAG_Y)) |
+                                          h) ^ FLAG_C;
+                            }
+                        }
+                        4
+                    }
+                }
+            }
+            1 => {
+                if y == 6 && z == 6 {
+                    self.halted = true;
+                    4
+                } else if y == 6 {
+                    /* LD (IX+d),r uses the real H and L */
+                    let addr = self.index_addr(mem, idx);
+                    let val = self.reg(z, Index::HL);
+                    mem.write(addr, val);
+                    7 + disp_cycles
+                } else if z == 6 {
+                    let addr = self.index_addr(mem, idx);
+                    let val = mem.read(addr);
+                    self.set_reg(y, Index::HL, val);
+                    7 + disp_cycles
+                } else {
+                    let val = self.reg(z, idx);
+                    self.set_reg(y, idx, val);
+                    4
+                }
+            }
+            2 => {
+                if z == 6 {
+                    let addr = self.index_addr(mem, idx);
+                    let val = mem.read(addr);
+                    self.alu(y, val);
+                    7 + disp_cycles
+                } else {
+                    let val = self.reg(z, idx);
+                    self.alu(y, val);
+                    4
+                }
+            }
+            _ => {
+                match z {
+                    0 => {
+                        if self.cond(y) {
+                            self.pc = self.pop(mem);
+                            11
+                        } else {
+                            5
+                        }
+                    }
+                    1 => {
+                        if q == 0 {
+                            let val = self.pop(mem);
+                            self.set_rp2(p, idx, val);
+                            10
+                        } else {
+                            match p {
+                                0 => {
+                                    self.pc = self.pop(mem);
+                                    10
+                                }
+                                1 => {
+                                    let (bc, de, hl) = (self.bc(), self.de(), self.hl());
+                                    let (alt_bc, alt_de, alt_hl) = (self.alt[1], self.alt[2], self.alt[3]);
+                                    self.set_bc(alt_bc);
+                                    self.set_de(alt_de);
+                                    self.set_hl(alt_hl);
+                                    self.alt[1] = bc;
+                                    self.alt[2] = de;
+                                    self.alt[3] = hl;
+                                    4
+                                }
+                                2 => {
+                                    self.pc = self.index(idx);
+                                    4
+                                }
+                                _ => {
+                                    self.sp = self.index(idx);
+                                    6
+                                }
+                            }
+                        }
+                    }
+                    2 => {
+                        let addr = self.fetch16(mem);
+                        if self.cond(y) {
+                            self.pc = addr;
+                        }
+                        10
+                    }
+                    3 => {
+                        match y {
+                            0 => {
+                                self.pc = self.fetch16(mem);
+                                10
+                            }
+                            /* 1 is the CB prefix, handled in step() */
+                            2 => {
+                                self.fetch(mem);
+                                11
+                            }
+                            3 => {
+                                self.fetch(mem);
+                                self.a = 0xFF;
+                                11
+                            }
+                            4 => {
+                                let sp = self.sp;
+                                let val = self.read16(mem, sp);
+                                let reg = self.index(idx);
+                                self.write16(mem, sp, reg);
+                                self.set_index(idx, val);
+                                19
+                            }
+                            5 => {
+                                /* EX DE,HL ignores the index prefix */
+                                let de = self.de();
+                                let hl = self.hl();
+                                self.set_de(hl);
+                                self.set_hl(de);
+                                4
+                            }
+                            6 => {
+                                self.iff1 = false;
+                                self.iff2 = false;
+                                4
+                            }
+                            _ => {
+                                self.iff1 = true;
+                                self.iff2 = true;
+                                4
+                            }
+                        }
+                    }
+                    4 => {
+                        let addr = self.fetch16(mem);
+                        if self.cond(y) {
+                            let pc = self.pc;
+                            self.push(mem, pc);
+                            self.pc = addr;
+                            17
+                        } else {
+                            10
+                        }
+                    }
+                    5 => {
+                        if q == 0 {
+                            let val = self.rp2(p, idx);
+                            self.push(mem, val);
+                            11
+                        } else {
+                            /* only CALL nn gets here, the prefixes are handled in step() */
+                            let addr = self.fetch16(mem);
+                            let pc = self.pc;
+                            self.push(mem, pc);
+                            self.pc = addr;
+                            17
+                        }
+                    }
+                    6 => {
+                        let val = self.fetch(mem);
+                        self.alu(y, val);
+                        7
+                    }
+                    _ => {
+                        let pc = self.pc;
+                        self.push(mem, pc);
+                        self.pc = (y as u16) * 8;
+                        11
+                    }
+                }
+            }
+        }
+    }
+
+    fn exec_cb<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M) -> u32 {
+        let op = self.fetch(mem);
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+
+        if z == 6 {
+            let addr = self.hl();
+            let val = mem.read(addr);
+            match x {
+                0 => {
+                    let res = self.rot(y, val);
+                    mem.write(addr, res);
+                    15
+                }
+                1 => {
+                    self.bit(y, val);
+                    12
+                }
+                2 => {
+                    mem.write(addr, val & !(1 << y));
+                    15
+                }
+                _ => {
+                    mem.write(addr, val | (1 << y));
+                    15
+                }
+            }
+        } else {
+            let val = self.reg(z, Index::HL);
+            match x {
+                0 => {
+                    let res = self.rot(y, val);
+                    self.set_reg(z, Index::HL, res);
+                }
+                1 => self.bit(y, val),
+                2 => self.set_reg(z, Index::HL, val & !(1 << y)),
+                _ => self.set_reg(z, Index::HL, val | (1 << y)),
+            }
+            8
+        }
+    }
+
+    /* DDCB/FDCB: the displacement comes before the opcode, and anything
+     * but BIT also copies the result into register z.
+     */
+    fn exec_index_cb<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M, idx: Index) -> u32 {
+        let addr = self.index_addr(mem, idx);
+        let op = self.fetch(mem);
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+
+        let val = mem.read(addr);
+        let res = match x {
+            0 => self.rot(y, val),
+            1 => {
+                self.bit(y, val);
+                return 20;
+            }
+            2 => val & !(1 << y),
+            _ => val | (1 << y),
+        };
+        mem.write(addr, res);
+        if z != 6 {
+            self.set_reg(z, Index::HL, res);
+        }
+        23
+    }
+
+    fn exec_ed<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M) -> u32 {
+        let op = self.fetch(mem);
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let p = y >> 1;
+        let q = y & 1;
+
+        match x {
+            1 => {
+                match z {
+                    0 => {
+                        let val = 0xFF;
+                        if y != 6 {
+                            self.set_reg(y, Index::HL, val);
+                        }
+                        self.f = (self.f & FLAG_C) | Z80::szxyp(val);
+                        12
+                    }
+                    1 => 12,
+                    2 => {
+                        let hl = self.hl();
+                        let val = self.rp(p, Index::HL);
+                        let res = if q == 0 {
+                            self.sbc16(hl, val)
+                        } else {
+                            self.adc16(hl, val)
+                        };
+                        self.set_hl(res);
+                        15
+                    }
+                    3 => {
+                        let addr = self.fetch16(mem);
+                        if q == 0 {
+                            let val = self.rp(p, Index::HL);
+                            self.write16(mem, addr, val);
+                        } else {
+                            let val = self.read16(mem, addr);
+                            self.set_rp(p, Index::HL, val);
+                        }
+                        20
+                    }
+                    4 => {
+                        let val = self.a;
+                        self.a = 0;
+                        self.a = self.sub8(val, false);
+                        8
+                    }
+                    5 => {
+                        /* RETN and RETI both restore IFF1 */
+                        self.iff1 = self.iff2;
+                        self.pc = self.pop(mem);
+                        14
+                    }
+                    6 => {
+                        self.im = match y & 3 {
+                            0 | 1 => 0,
+                            2 => 1,
+                            _ => 2,
+                        };
+                        8
+                    }
+                    _ => {
+                        match y {
+                            0 => {
+                                self.i = self.a;
+                                9
+                            }
+                            1 => {
+                                self.r = self.a;
+                                9
+                            }
+                            2 | 3 => {
+                                self.a = if y == 2 { self.i } else { self.r };
+                                self.f = (self.f & FLAG_C) | Z80::szxy(self.a);
+                                if self.iff2 {
+                                    self.f |= FLAG_PV;
+                                }
+                                9
+                            }
+                            4 | 5 => {
+                                let addr = self.hl();
+                                let val = mem.read(addr);
+                                let res = if y == 4 {
+                                    /* RRD */
+                                    let res = (self.a << 4) | (val >> 4);
+                                    self.a = (self.a & 0xF0) | (val & 0x0F);
+                                    res
+                                } else {
+                                    /* RLD */
+                                    let res = (val << 4) | (self.a & 0x0F);
+                                    self.a = (self.a & 0xF0) | (val >> 4);
+                                    res
+                                };
+                                mem.write(addr, res);
+                                self.f = (self.f & FLAG_C) | Z80::szxyp(self.a);
+                                18
+                            }
+                            _ => 8,
+                        }
+                    }
+                }
+            }
+            2 if z <= 3 && y >= 4 => self.exec_block(mem, y, z),
+            _ => 8,
+        }
+    }
+
+    /* LDI/LDD/CPI/CPD/INI/IND/OUTI/OUTD and their repeating forms. */
+    fn exec_block<M: Memory<u8> + ?Sized>(&mut self, mem: &mut M, y: u8, z: u8) -> u32 {
+        let decrement = y & 1 != 0;
+        let repeat = y & 2 != 0;
+        let hl = self.hl();
+        let next_hl = if decrement { hl.wrapping_sub(1) } else { hl.wrapping_add(1) };
+
+        let again = match z {
+            0 => {
+                let val = mem.read(hl);
+                let de = self.de();
+                mem.write(de, val);
+                self.set_de(if decrement { de.wrapping_sub(1) } else { de.wrapping_add(1) });
+                self.set_hl(next_hl);
+                let bc = self.bc().wrapping_sub(1);
+                self.set_bc(bc);
+
+                let n = val.wrapping_add(self.a);
+                self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_C)) | (n & FLAG_X) | ((n << 4) & FLAG_Y);
+                if bc != 0 {
+                    self.f |= FLAG_PV;
+                }
+                bc != 0
+            }
+            1 => {
+                let val = mem.read(hl);
+                let res = self.a.wrapping_sub(val);
+                self.set_hl(next_hl);
+                let bc = self.bc().wrapping_sub(1);
+                self.set_bc(bc);
+
+                let mut f = (self.f & FLAG_C) | FLAG_N | (res & FLAG_S);
+                if res == 0 {
+                    f |= FLAG_Z;
+                }
+                if (self.a ^ val ^ res) & 0x10 != 0 {
+                    f |= FLAG_H;
+                }
+                if bc != 0 {
+                    f |= FLAG_PV;
+                }
+                self.f = f;
+                bc != 0 && res != 0
+            }
+            _ => {
+                /* nothing on the I/O bus: INs read $FF, OUTs are dropped */
+                if z == 2 {
+                    mem.write(hl, 0xFF);
+                } else {
+                    mem.read(hl);
+                }
+                self.set_hl(next_hl);
+                self.b = self.b.wrapping_sub(1);
+                self.f = Z80::szxy(self.b) | FLAG_N | (self.f & FLAG_C);
+                self.b != 0
+            }
+        };
+
+        if repeat && again {
+            self.pc = self.pc.wrapping_sub(2);
+            21
+        } else {
+            16
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r6502::memory::Memory;
+
+    struct Ram(Vec<u8>);
+
+    impl Memory<u8> for Ram {
+        fn read_without_mm(&mut self, addr: u16) -> u8 {
+            self.0[addr as usize]
+        }
+
+        fn write_without_mm(&mut self, addr: u16, val: u8) {
+            self.0[addr as usize] = val;
+        }
+    }
+
+    /* Runs a program from $0000 up to its HALT. */
+    fn run(program: &[u8]) -> (Z80, Ram) {
+        let mut ram = Ram(vec![0; 0x10000]);
+        ram.0[..program.len()].copy_from_slice(program);
+        let mut z80 = Z80::new();
+        z80.reset();
+        for _ in 0..1000 {
+            if z80.halted {
+                break;
+            }
+            z80.step(&mut ram);
+        }
+        assert!(z80.halted, "program did not halt");
+        (z80, ram)
+    }
+
+    fn flags(z80: &Z80) -> u8 {
+        z80.f & !(FLAG_X | FLAG_Y)
+    }
+
+    #[test]
+    fn daa_after_add() {
+        /* LD A,$15; ADD A,$27; DAA */
+        let (z80, _) = run(&[0x3E, 0x15, 0xC6, 0x27, 0x27, 0x76]);
+        assert_eq!(z80.a, 0x42);
+        assert_eq!(flags(&z80), FLAG_H | FLAG_PV);
+
+        /* LD A,$99; ADD A,$01; DAA */
+        let (z80, _) = run(&[0x3E, 0x99, 0xC6, 0x01, 0x27, 0x76]);
+        assert_eq!(z80.a, 0x00);
+        assert_eq!(flags(&z80), FLAG_Z | FLAG_H | FLAG_PV | FLAG_C);
+    }
+
+    #[test]
+    fn daa_after_sub() {
+        /* LD A,$42; SUB $15; DAA */
+        let (z80, _) = run(&[0x3E, 0x42, 0xD6, 0x15, 0x27, 0x76]);
+        assert_eq!(z80.a, 0x27);
+        assert_eq!(flags(&z80), FLAG_PV | FLAG_N);
+
+        /* LD A,$10; SUB $20; DAA */
+        let (z80, _) = run(&[0x3E, 0x10, 0xD6, 0x20, 0x27, 0x76]);
+        assert_eq!(z80.a, 0x90);
+        assert_eq!(flags(&z80), FLAG_S | FLAG_PV | FLAG_N | FLAG_C);
+    }
+
+    #[test]
+    fn adc_half_carry_and_overflow() {
+        /* LD A,$7F; SCF; ADC A,$00 */
+        let (z80, _) = run(&[0x3E, 0x7F, 0x37, 0xCE, 0x00, 0x76]);
+        assert_eq!(z80.a, 0x80);
+        assert_eq!(flags(&z80), FLAG_S | FLAG_H | FLAG_PV);
+
+        /* LD A,$FF; SCF; ADC A,$00 */
+        let (z80, _) = run(&[0x3E, 0xFF, 0x37, 0xCE, 0x00, 0x76]);
+        assert_eq!(z80.a, 0x00);
+        assert_eq!(flags(&z80), FLAG_Z | FLAG_H | FLAG_C);
+    }
+
+    #[test]
+    fn sbc_half_carry_and_overflow() {
+        /* LD A,$80; SCF; SBC A,$00 */
+        let (z80, _) = run(&[0x3E, 0x80, 0x37, 0xDE, 0x00, 0x76]);
+        assert_eq!(z80.a, 0x7F);
+        assert_eq!(flags(&z80), FLAG_H | FLAG_PV | FLAG_N);
+
+        /* LD A,$00; SCF; SBC A,$00 */
+        let (z80, _) = run(&[0x3E, 0x00, 0x37, 0xDE, 0x00, 0x76]);
+        assert_eq!(z80.a, 0xFF);
+        assert_eq!(flags(&z80), FLAG_S | FLAG_H | FLAG_N | FLAG_C);
+    }
+
+    #[test]
+    fn adc_sbc_16() {
+        /* LD HL,$7FFF; LD BC,$0000; SCF; ADC HL,BC */
+        let (z80, _) = run(&[0x21, 0xFF, 0x7F, 0x01, 0x00, 0x00, 0x37, 0xED, 0x4A, 0x76]);
+        assert_eq!((z80.h, z80.l), (0x80, 0x00));
+        assert_eq!(flags(&z80), FLAG_S | FLAG_H | FLAG_PV);
+
+        /* LD HL,$8000; LD DE,$0001; OR A; SBC HL,DE */
+        let (z80, _) = run(&[0x21, 0x00, 0x80, 0x11, 0x01, 0x00, 0xB7, 0xED, 0x52, 0x76]);
+        assert_eq!((z80.h, z80.l), (0x7F, 0xFF));
+        assert_eq!(flags(&z80), FLAG_H | FLAG_PV | FLAG_N);
+
+        /* LD HL,$0000; LD DE,$0001; OR A; SBC HL,DE */
+        let (z80, _) = run(&[0x21, 0x00, 0x00, 0x11, 0x01, 0x00, 0xB7, 0xED, 0x52, 0x76]);
+        assert_eq!((z80.h, z80.l), (0xFF, 0xFF));
+        assert_eq!(flags(&z80), FLAG_S | FLAG_H | FLAG_N | FLAG_C);
+    }
+
+    #[test]
+    fn block_moves() {
+        /* LD HL,$8000; LD DE,$9000; LD BC,$0002; LDI */
+        let mut program = vec![0x21, 0x00, 0x80, 0x11, 0x00, 0x90, 0x01, 0x02, 0x00, 0xED, 0xA0,
+                               0x76];
+        let (z80, ram) = run(&program);
+        assert_eq!(ram.0[0x9000], ram.0[0x8000]);
+        assert_eq!((z80.h, z80.l, z80.d, z80.e, z80.b, z80.c), (0x80, 0x01, 0x90, 0x01, 0, 1));
+        assert_eq!(z80.f & (FLAG_H | FLAG_PV | FLAG_N), FLAG_PV);
+
+        /* then LDI again, leaving BC at zero */
+        program.insert(11, 0xA0);
+        program.insert(11, 0xED);
+        let (z80, _) = run(&program);
+        assert_eq!((z80.b, z80.c), (0, 0));
+        assert_eq!(z80.f & (FLAG_H | FLAG_PV | FLAG_N), 0);
+
+        /* LD HL,$0100; LD DE,$0203; LD BC,$0004; LDDR, copying $00FD-$0100 down to $0200-$0203 */
+        let mut program = vec![0x21, 0x00, 0x01, 0x11, 0x03, 0x02, 0x01, 0x04, 0x00, 0xED, 0xB8,
+                               0x76];
+        program.resize(0x101, 0);
+        program[0xFD..0x101].copy_from_slice(&[1, 2, 3, 4]);
+        let (z80, ram) = run(&program);
+        assert_eq!(&ram.0[0x200..0x204], &[1, 2, 3, 4]);
+        assert_eq!((z80.h, z80.l, z80.d, z80.e, z80.b, z80.c), (0x00, 0xFC, 0x01, 0xFF, 0, 0));
+        assert_eq!(z80.f & FLAG_PV, 0);
+    }
+
+    #[test]
+    fn block_compare() {
+        /* LD A,$33; LD HL,$0100; LD BC,$0004; CPIR, finding the $33 at $0102 */
+        let mut program = vec![0x3E, 0x33, 0x21, 0x00, 0x01, 0x01, 0x04, 0x00, 0xED, 0xB1, 0x76];
+        program.resize(0x104, 0);
+        program[0x100..0x104].copy_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+        let (z80, _) = run(&program);
+        assert_eq!((z80.h, z80.l, z80.b, z80.c), (0x01, 0x03, 0, 1));
+        assert_eq!(z80.f & (FLAG_Z | FLAG_PV | FLAG_N), FLAG_Z | FLAG_PV | FLAG_N);
+    }
+
+    #[test]
+    fn ldir_timing() {
+        let mut ram = Ram(vec![0; 0x10000]);
+        /* LD BC,$0002; LDIR */
+        ram.0[..5].copy_from_slice(&[0x01, 0x02, 0x00, 0xED, 0xB0]);
+        let mut z80 = Z80::new();
+        z80.reset();
+        assert_eq!(z80.step(&mut ram), 10);
+        assert_eq!(z80.step(&mut ram), 21);
+        assert_eq!(z80.pc, 3);
+        assert_eq!(z80.step(&mut ram), 16);
+        assert_eq!(z80.pc, 5);
+    }
+
+    #[test]
+    fn cb_prefix() {
+        /* LD B,$81; RLC B */
+        let (z80, _) = run(&[0x06, 0x81, 0xCB, 0x00, 0x76]);
+        assert_eq!(z80.b, 0x03);
+        assert_eq!(flags(&z80), FLAG_PV | FLAG_C);
+
+        /* LD A,$80; BIT 7,A; BIT 0,A */
+        let (z80, _) = run(&[0x3E, 0x80, 0xCB, 0x7F, 0x76]);
+        assert_eq!(z80.f & (FLAG_Z | FLAG_H | FLAG_N), FLAG_H);
+        let (z80, _) = run(&[0x3E, 0x80, 0xCB, 0x47, 0x76]);
+        assert_eq!(z80.f & (FLAG_Z | FLAG_H | FLAG_N), FLAG_Z | FLAG_H);
+
+        /* LD HL,$0100; SET 3,(HL); SRL (HL) */
+        let (_, ram) = run(&[0x21, 0x00, 0x01, 0xCB, 0xDE, 0xCB, 0x3E, 0x76]);
+        assert_eq!(ram.0[0x100], 0x04);
+    }
+
+    #[test]
+    fn ed_prefix() {
+        /* LD A,$01; NEG */
+        let (z80, _) = run(&[0x3E, 0x01, 0xED, 0x44, 0x76]);
+        assert_eq!(z80.a, 0xFF);
+        assert_eq!(flags(&z80), FLAG_S | FLAG_H | FLAG_N | FLAG_C);
+
+        /* LD A,$80; NEG */
+        let (z80, _) = run(&[0x3E, 0x80, 0xED, 0x44, 0x76]);
+        assert_eq!(z80.a, 0x80);
+        assert_eq!(flags(&z80), FLAG_S | FLAG_PV | FLAG_N | FLAG_C);
+
+        /* LD BC,$1234; LD ($0100),BC; LD DE,($0100) */
+        let (z80, ram) = run(&[0x01, 0x34, 0x12, 0xED, 0x43, 0x00, 0x01, 0xED, 0x5B, 0x00, 0x01,
+                               0x76]);
+        assert_eq!(&ram.0[0x100..0x102], &[0x34, 0x12]);
+        assert_eq!((z80.d, z80.e), (0x12, 0x34));
+    }
+
+    #[test]
+    fn index_prefixes() {
+        /* LD IX,$0100; LD (IX+2),$55; LD A,(IX+2); LD IY,$0104; LD B,(IY-2) */
+        let (z80, ram) = run(&[0xDD, 0x21, 0x00, 0x01, 0xDD, 0x36, 0x02, 0x55, 0xDD, 0x7E, 0x02,
+                               0xFD, 0x21, 0x04, 0x01, 0xFD, 0x46, 0xFE, 0x76]);
+        assert_eq!(ram.0[0x102], 0x55);
+        assert_eq!((z80.a, z80.b), (0x55, 0x55));
+        assert_eq!((z80.ix, z80.iy), (0x0100, 0x0104));
+
+        /* LD IX,$0100; SET 0,(IX+3); LD IY,$0100; RLC (IY+3) */
+        let (z80, ram) = run(&[0xDD, 0x21, 0x00, 0x01, 0xDD, 0xCB, 0x03, 0xC6, 0xFD, 0x21, 0x00,
+                               0x01, 0xFD, 0xCB, 0x03, 0x06, 0x76]);
+        assert_eq!(ram.0[0x103], 0x02);
+        assert_eq!(z80.f & FLAG_C, 0);
+
+        /* LD HL,$1111; LD IX,$2000; ADD IX,IX leaves HL alone */
+        let (z80, _) = run(&[0x21, 0x11, 0x11, 0xDD, 0x21, 0x00, 0x20, 0xDD, 0x29, 0x76]);
+        assert_eq!(z80.ix, 0x4000);
+        assert_eq!((z80.h, z80.l), (0x11, 0x11));
+    }
+}